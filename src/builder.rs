@@ -1,135 +1,2334 @@
 use {
+    crate::util::{inherited_vis, option_inner, snake_case},
     proc_macro2::TokenStream,
-    quote::{quote, quote_spanned},
-    syn::{Data, DeriveInput, Error, Fields, Ident, Result, spanned::Spanned},
+    quote::{format_ident, quote, quote_spanned},
+    syn::{
+        Data, DeriveInput, Error, Expr, ExprClosure, Field, Fields, GenericArgument, GenericParam,
+        Generics, Ident, Member, Pat, Path, PathArguments, Result, Token, Type, Variant,
+        Visibility, parse_quote, spanned::Spanned,
+    },
 };
 
+pub(crate) struct BuilderConfig {
+    typestate: bool,
+    pattern: BuilderPattern,
+    error: Option<Type>,
+    validate: Option<Path>,
+    conversion: Option<SetterConversion>,
+    /// `#[builder(const)]`: `const fn` setters and a `build_const()`.
+    constant: bool,
+    /// `#[builder(serde)]`: the builder derives `serde::Deserialize`.
+    serde: bool,
+}
+
+/// How setters and `build()` take the builder, picked with
+/// `#[builder(pattern = "...")]`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BuilderPattern {
+    /// `fn x(mut self, ..) -> Self` and `build(self)`.
+    Owned,
+    /// `fn x(&mut self, ..) -> &mut Self` and `build(&self)`.
+    Mutable,
+    /// `fn x(&self, ..) -> Self`, cloning the builder, and `build(&self)`.
+    Immutable,
+}
+
+impl BuilderPattern {
+    fn receiver(self) -> TokenStream {
+        match self {
+            Self::Owned => quote! { mut self },
+            Self::Mutable => quote! { &mut self },
+            Self::Immutable => quote! { &self },
+        }
+    }
+
+    fn return_ty(self) -> TokenStream {
+        match self {
+            Self::Owned | Self::Immutable => quote! { Self },
+            Self::Mutable => quote! { &mut Self },
+        }
+    }
+
+    /// The builder a setter writes to, and the statement that makes it available.
+    fn target(self) -> (TokenStream, TokenStream) {
+        match self {
+            Self::Owned | Self::Mutable => (quote! { self }, quote! {}),
+            Self::Immutable => (
+                quote! { new },
+                quote! { let mut new = ::core::clone::Clone::clone(self); },
+            ),
+        }
+    }
+
+    /// Reads a stored field in `build()`, cloning it when the builder is only
+    /// borrowed.
+    fn take(self, field_name: &Ident) -> TokenStream {
+        match self {
+            Self::Owned => quote! { self.#field_name },
+            Self::Mutable | Self::Immutable => {
+                quote! { ::core::clone::Clone::clone(&self.#field_name) }
+            }
+        }
+    }
+
+    fn build_receiver(self) -> TokenStream {
+        match self {
+            Self::Owned => quote! { self },
+            Self::Mutable | Self::Immutable => quote! { &self },
+        }
+    }
+}
+
+impl BuilderConfig {
+    /// Converts a generated builder error into the `#[builder(error = ...)]`
+    /// type, if one was given.
+    fn convert_error(&self, error: TokenStream) -> TokenStream {
+        match &self.error {
+            Some(_) => quote! { ::core::convert::From::from(#error) },
+            None => error,
+        }
+    }
+}
+
+pub(crate) struct FieldConfig {
+    default: Option<FieldDefault>,
+    validate: Option<Path>,
+    conversion: Option<SetterConversion>,
+    each: Option<Ident>,
+    nested: bool,
+    skip: bool,
+    setter: SetterConfig,
+    /// `#[builder(name = "...")]`, naming a tuple field in the builder.
+    name: Option<Ident>,
+    /// The `default_with` initializer returns a `Result`.
+    fallible: bool,
+    /// The `default_with` initializer is an `async fn`.
+    asynchronous: bool,
+    /// `#[builder(serde(...))]`, forwarded to the builder field.
+    serde: Option<TokenStream>,
+}
+
+/// `#[builder(setter(name = "...", vis = "...", prefix = "..."))]`.
+#[derive(Default)]
+pub(crate) struct SetterConfig {
+    name: Option<Ident>,
+    vis: Option<Visibility>,
+    prefix: Option<String>,
+}
+
+pub(crate) enum FieldDefault {
+    /// `#[builder(default)]`, falls back to `Default::default()`.
+    Trait,
+    /// `#[builder(default = expr)]`, evaluated after the fields declared before it.
+    Expr(Expr),
+    /// `#[builder(default_with = path)]`, calls `path()`.
+    With(Path),
+}
+
+#[derive(Clone)]
+pub(crate) enum SetterConversion {
+    /// `#[builder(into)]`, the setter takes `impl Into<T>`.
+    Into,
+    /// `#[builder(try_into)]`, the setter takes any `V: TryInto<T>` and returns
+    /// `Result<Self, V::Error>`.
+    TryInto,
+    /// `#[builder(with = |a: A, b: B| ...)]`, the setter takes the closure's
+    /// parameters and stores what it returns.
+    With(ExprClosure),
+}
+
+/// What a collection field with `#[builder(each = "...")]` holds.
+enum CollectionItem<'a> {
+    /// `Vec<T>`, `HashSet<T>`, `VecDeque<T>`, ...
+    Single(&'a Type),
+    /// `HashMap<K, V>`, `BTreeMap<K, V>`, ...
+    Entry(&'a Type, &'a Type),
+}
+
+/// How a setter turns its parameters into the value it stores.
+struct SetterInput {
+    generics: TokenStream,
+    params: TokenStream,
+    /// Statements binding the converted value to `value`.
+    convert: TokenStream,
+    /// Error type of a fallible conversion; the setter then returns a `Result`.
+    error: Option<TokenStream>,
+}
+
+impl SetterInput {
+    /// The setter's return type, given what it returns on success.
+    fn return_ty(&self, ok: TokenStream) -> TokenStream {
+        match &self.error {
+            Some(error) => quote! { ::std::result::Result<#ok, #error> },
+            None => ok,
+        }
+    }
+
+    /// The setter's final expression, given what it returns on success.
+    fn finish(&self, ok: TokenStream) -> TokenStream {
+        match &self.error {
+            Some(_) => quote! { ::std::result::Result::Ok(#ok) },
+            None => ok,
+        }
+    }
+}
+
+/// A struct field together with its parsed `#[builder(...)]` configuration.
+struct BuilderField<'a> {
+    field: &'a Field,
+    /// Name of the field in the builder: the field's own, or `_0`, `_1`, ...
+    /// (or a `#[builder(name = "...")]` alias) for tuple fields.
+    ident: Ident,
+    /// How the field is accessed on the built value.
+    member: Member,
+    /// The type stored in the builder and taken by the setter. For `Option<T>`
+    /// fields this is `T`.
+    value_ty: &'a Type,
+    optional: bool,
+    config: FieldConfig,
+    /// Set for `#[builder(nested)]` fields, which store the field type's own builder.
+    nested: Option<NestedBuilder>,
+}
+
+/// The builder and error types generated for a `#[builder(nested)]` field's type.
+struct NestedBuilder {
+    builder: Type,
+    error: Path,
+}
+
+impl BuilderField<'_> {
+    /// Whether `build()` has to fail when the field was never set. Nested
+    /// fields count as required since building them can fail.
+    fn is_required(&self) -> bool {
+        self.nested.is_some() || (!self.optional && self.config.default.is_none())
+    }
+
+    /// Whether the field has a slot in the builder; `#[builder(skip)]` fields
+    /// are always built from their default.
+    fn is_stored(&self) -> bool {
+        !self.config.skip
+    }
+
+    /// Name of the field's main setter, `#[builder(setter(name = ...))]` or
+    /// the field name with an optional prefix.
+    fn setter_name(&self) -> Ident {
+        let setter = &self.config.setter;
+
+        match (&setter.name, &setter.prefix) {
+            (Some(name), _) => name.clone(),
+            (None, Some(prefix)) => format_ident!("{}{}", prefix, self.ident),
+            (None, None) => self.ident.clone(),
+        }
+    }
+
+    /// Visibility of the field's setters, `setter(vis = ...)` or the one the
+    /// field inherits.
+    fn setter_vis(&self) -> &Visibility {
+        self.config.setter.vis.as_ref().unwrap_or(&self.field.vis)
+    }
+
+    /// The field's own doc comments, or a generic one if it has none.
+    fn setter_docs(&self) -> TokenStream {
+        let docs: Vec<_> = self
+            .field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("doc"))
+            .collect();
+
+        if docs.is_empty() {
+            let doc_comment = format!("Set the `{}` field.", self.ident);
+            quote! { #[doc = #doc_comment] }
+        } else {
+            quote! { #(#docs)* }
+        }
+    }
+
+    /// Expression producing the field's value when its setter was never called.
+    fn default_value(&self) -> TokenStream {
+        match &self.config.default {
+            Some(FieldDefault::Trait) => quote! { ::core::default::Default::default() },
+            Some(FieldDefault::Expr(expr)) => quote! { #expr },
+            Some(FieldDefault::With(path)) if self.config.asynchronous => quote! { #path().await },
+            Some(FieldDefault::With(path)) => quote! { #path() },
+            None => quote! { ::core::option::Option::None },
+        }
+    }
+
+    /// Like `default_value`, but returns early from `build` with an
+    /// `InitFailed` error when a `fallible` initializer fails.
+    fn checked_default(&self, config: &BuilderConfig, error_name: &Ident) -> TokenStream {
+        let default = self.default_value();
+
+        if !self.config.fallible {
+            return default;
+        }
+
+        let field_name_str = self.ident.to_string();
+        let error = config.convert_error(quote! {
+            #error_name::InitFailed {
+                field: ::std::string::String::from(#field_name_str),
+                message: ::std::string::ToString::to_string(&err),
+            }
+        });
+
+        quote! {
+            match #default {
+                ::std::result::Result::Ok(value) => value,
+                ::std::result::Result::Err(err) => return ::std::result::Result::Err(#error),
+            }
+        }
+    }
+
+    fn setter_input(&self) -> SetterInput {
+        let value_ty = self.value_ty;
+
+        match &self.config.conversion {
+            None => SetterInput {
+                generics: quote! {},
+                params: quote! { value: #value_ty },
+                convert: quote! {},
+                error: None,
+            },
+            Some(SetterConversion::Into) => SetterInput {
+                generics: quote! {},
+                params: quote! { value: impl ::core::convert::Into<#value_ty> },
+                convert: quote! { let value = ::core::convert::Into::into(value); },
+                error: None,
+            },
+            Some(SetterConversion::TryInto) => SetterInput {
+                generics: quote! { <V: ::core::convert::TryInto<#value_ty>> },
+                params: quote! { value: V },
+                convert: quote! { let value = ::core::convert::TryInto::try_into(value)?; },
+                error: Some(quote! { <V as ::core::convert::TryInto<#value_ty>>::Error }),
+            },
+            Some(SetterConversion::With(closure)) => {
+                let (names, types): (Vec<_>, Vec<_>) = closure
+                    .inputs
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, input)| match input {
+                        Pat::Type(pat_type) => {
+                            let name = match &*pat_type.pat {
+                                Pat::Ident(pat_ident) => pat_ident.ident.clone(),
+                                _ => format_ident!("arg{}", idx),
+                            };
+                            (name, &*pat_type.ty)
+                        }
+                        _ => unreachable!("checked by `parse_field_attrs`"),
+                    })
+                    .unzip();
+
+                SetterInput {
+                    generics: quote! {},
+                    params: quote! { #(#names: #types),* },
+                    convert: quote! {
+                        #[allow(clippy::redundant_closure_call)]
+                        let value: #value_ty = (#closure)(#(#names),*);
+                    },
+                    error: None,
+                }
+            }
+        }
+    }
+
+    /// Expression turning the stored `Option<value_ty>` into the field's value,
+    /// falling back to the configured default. Only valid for fields that are
+    /// not required.
+    fn resolve_optional(&self, stored: TokenStream) -> TokenStream {
+        self.resolve_with(stored, self.default_value())
+    }
+
+    /// `resolve_optional` with the fallback expression given.
+    fn resolve_with(&self, stored: TokenStream, default: TokenStream) -> TokenStream {
+        if !self.is_stored() {
+            default
+        } else if self.optional && self.config.default.is_none() {
+            stored
+        } else if self.optional {
+            quote! {
+                match #stored {
+                    ::core::option::Option::Some(value) => ::core::option::Option::Some(value),
+                    ::core::option::Option::None => #default,
+                }
+            }
+        } else {
+            quote! {
+                match #stored {
+                    ::core::option::Option::Some(value) => value,
+                    ::core::option::Option::None => #default,
+                }
+            }
+        }
+    }
+
+    /// Initializer for the field in the built value's struct expression.
+    fn init(&self) -> TokenStream {
+        let ident = &self.ident;
+
+        match &self.member {
+            Member::Named(named) if named == ident => quote! { #ident },
+            member => quote! { #member: #ident },
+        }
+    }
+}
+
+/// What a generated builder builds: a struct, or one variant of an enum.
+struct BuilderTarget<'a> {
+    input: &'a DeriveInput,
+    variant: Option<&'a Ident>,
+    /// `Foo` or `Shape::Circle`, the path the built value is constructed with.
+    path: TokenStream,
+    builder_name: Ident,
+    /// The associated function on the input type that returns a new builder.
+    builder_fn: Ident,
+}
+
+impl BuilderTarget<'_> {
+    fn error_name(&self) -> Ident {
+        format_ident!("{}Error", self.builder_name)
+    }
+
+    /// Name of the built type in docs and error messages.
+    fn display_name(&self) -> String {
+        match self.variant {
+            Some(variant) => format!("{}::{}", self.input.ident, variant),
+            None => self.input.ident.to_string(),
+        }
+    }
+}
+
 pub(crate) fn expand_builder(input: DeriveInput) -> Result<TokenStream> {
+    let config = parse_builder_config(&input.attrs)?;
     let name = &input.ident;
-    let vis = &input.vis;
 
-    let fields = match &input.data {
-        Data::Struct(data) => match &data.fields {
-            Fields::Named(fields) => &fields.named,
-            Fields::Unnamed(_) => {
+    match &input.data {
+        Data::Struct(data) => {
+            if let Fields::Unit = data.fields {
                 return Err(Error::new_spanned(
                     &input,
-                    "Builder only supports structs with named fields",
+                    "Builder cannot be derived for unit structs",
                 ));
             }
-            Fields::Unit => {
+
+            let target = BuilderTarget {
+                input: &input,
+                variant: None,
+                path: quote! { #name },
+                builder_name: format_ident!("{}Builder", name),
+                builder_fn: format_ident!("builder"),
+            };
+
+            expand_target(&target, &config, &data.fields)
+        }
+        Data::Enum(data_enum) => {
+            if let Some(attr) = data_enum
+                .variants
+                .iter()
+                .flat_map(|variant| &variant.attrs)
+                .find(|attr| attr.path().is_ident("builder"))
+            {
                 return Err(Error::new_spanned(
-                    &input,
-                    "Builder cannot be derived for unit structs",
+                    attr,
+                    "`builder` attributes are not supported on enum variants, \
+                     configure the enum or the variant's fields instead",
                 ));
             }
-        },
-        Data::Enum(data_enum) => {
+
+            let variants: Vec<&Variant> = data_enum
+                .variants
+                .iter()
+                .filter(|variant| !matches!(variant.fields, Fields::Unit))
+                .collect();
+
+            if variants.is_empty() {
+                return Err(Error::new_spanned(
+                    data_enum.enum_token,
+                    "Builder needs at least one enum variant with fields",
+                ));
+            }
+
+            let mut expanded = TokenStream::new();
+            for variant in variants {
+                let variant_name = &variant.ident;
+                let target = BuilderTarget {
+                    input: &input,
+                    variant: Some(variant_name),
+                    path: quote! { #name::#variant_name },
+                    builder_name: format_ident!("{}{}Builder", name, variant_name),
+                    builder_fn: format_ident!("{}_builder", snake_case(variant_name)),
+                };
+
+                expanded.extend(expand_target(&target, &config, &variant.fields)?);
+            }
+
+            Ok(expanded)
+        }
+        Data::Union(data_union) => Err(Error::new_spanned(
+            data_union.union_token,
+            "Builder cannot be derived for unions",
+        )),
+    }
+}
+
+/// Parses the fields of a struct or enum variant and generates its builder.
+fn expand_target(
+    target: &BuilderTarget,
+    config: &BuilderConfig,
+    fields: &Fields,
+) -> Result<TokenStream> {
+    let mut builder_fields = Vec::new();
+    let mut errors: Vec<Error> = Vec::new();
+
+    for (idx, field) in fields.iter().enumerate() {
+        match parse_field_attrs(&field.attrs) {
+            Ok(mut field_config) => {
+                let (ident, member) = match &field.ident {
+                    Some(ident) => {
+                        if let Some(alias) = &field_config.name {
+                            errors.push(Error::new_spanned(
+                                alias,
+                                "`name` only applies to tuple fields, \
+                                 use `setter(name = \"...\")` to rename a setter",
+                            ));
+                            continue;
+                        }
+
+                        (ident.clone(), Member::Named(ident.clone()))
+                    }
+                    None => (
+                        field_config
+                            .name
+                            .clone()
+                            .unwrap_or_else(|| format_ident!("_{}", idx, span = field.ty.span())),
+                        Member::Unnamed(idx.into()),
+                    ),
+                };
+
+                if field_config.conversion.is_none() && !field_config.nested {
+                    field_config.conversion = config.conversion.clone();
+                }
+
+                if field_config.setter.vis.is_none() {
+                    let vis = inherited_vis(target.input, field, target.variant.is_some());
+                    field_config.setter.vis = Some(vis.clone());
+                }
+
+                let nested = if field_config.nested {
+                    match nested_builder(&field.ty) {
+                        Ok(nested) => Some(nested),
+                        Err(err) => {
+                            errors.push(err);
+                            continue;
+                        }
+                    }
+                } else {
+                    None
+                };
+
+                if let Some(each) = &field_config.each {
+                    if option_inner(&field.ty).is_some() {
+                        errors.push(Error::new_spanned(
+                            each,
+                            "`each` cannot be used on `Option` fields",
+                        ));
+                        continue;
+                    }
+
+                    if &ident == each {
+                        errors.push(Error::new_spanned(
+                            each,
+                            "`each` needs a name different from the field's own setter",
+                        ));
+                        continue;
+                    }
+
+                    if field_config.default.is_none() {
+                        field_config.default = Some(FieldDefault::Trait);
+                    }
+                }
+
+                let optional_inner = option_inner(&field.ty);
+                builder_fields.push(BuilderField {
+                    field,
+                    ident,
+                    member,
+                    value_ty: optional_inner.unwrap_or(&field.ty),
+                    optional: optional_inner.is_some(),
+                    config: field_config,
+                    nested,
+                });
+            }
+            Err(err) => errors.push(err),
+        }
+    }
+
+    if let Some(mut combined_error) = errors.pop() {
+        for err in errors {
+            combined_error.combine(err);
+        }
+
+        return Err(combined_error);
+    }
+
+    if config.constant {
+        check_const(target, config, &builder_fields)?;
+    }
+
+    check_method_names(target, config, &builder_fields)?;
+
+    if !config.serde
+        && let Some(field) = builder_fields.iter().find(|f| f.config.serde.is_some())
+    {
+        return Err(Error::new_spanned(
+            field.field,
+            "`serde(...)` on a field needs `#[builder(serde)]` on the struct",
+        ));
+    }
+
+    if config.typestate {
+        if config.pattern != BuilderPattern::Owned {
+            return Err(Error::new_spanned(
+                &target.input.ident,
+                "`typestate` builders change type with every setter and only support the owned pattern",
+            ));
+        }
+
+        if let Some(field) = builder_fields.iter().find(|f| f.nested.is_some()) {
+            return Err(Error::new_spanned(
+                field.field,
+                "`nested` fields are not supported by `typestate` builders",
+            ));
+        }
+
+        if let Some(field) = builder_fields
+            .iter()
+            .find(|f| f.config.fallible || f.config.asynchronous)
+        {
+            return Err(Error::new_spanned(
+                field.field,
+                "`fallible` and `async` initializers are not supported by `typestate` builders",
+            ));
+        }
+
+        if config.serde {
             return Err(Error::new_spanned(
-                data_enum.enum_token,
-                "Builder cannot be derived for enums",
+                &target.input.ident,
+                "`serde` is not supported by `typestate` builders, \
+                 which track required fields in the builder's type",
             ));
         }
-        Data::Union(data_union) => {
+
+        if let Some(error) = &config.error
+            && !has_validators(config, &builder_fields)
+        {
             return Err(Error::new_spanned(
-                data_union.union_token,
-                "Builder cannot be derived for unions",
+                error,
+                "`error` has no effect with `typestate` unless a `validate` hook can make `build()` fail",
             ));
         }
+
+        expand_typestate_builder(target, config, &builder_fields)
+    } else {
+        expand_runtime_builder(target, config, &builder_fields)
+    }
+}
+
+/// Rejects setters that would clash with the builder's own methods, like a
+/// field named `merge`.
+fn check_method_names(
+    target: &BuilderTarget,
+    config: &BuilderConfig,
+    fields: &[BuilderField],
+) -> Result<()> {
+    let reserved: &[&str] = if config.typestate {
+        &["build"]
+    } else if fields.iter().any(|f| f.config.asynchronous) {
+        &["merge", "build_async"]
+    } else if fields.iter().any(|f| f.config.fallible) {
+        &["merge", "try_build"]
+    } else if config.constant {
+        &["merge", "build", "build_const"]
+    } else {
+        &["merge", "build"]
     };
+    let builder_name = &target.builder_name;
+    let mut errors = Vec::new();
+
+    for f in fields.iter().filter(|f| f.is_stored()) {
+        let setter_name = f.setter_name();
+        let mut names = vec![(setter_name.clone(), "`setter(name = \"...\")`")];
+
+        if f.nested.is_some() {
+            names.push((
+                format_ident!("{}_with", setter_name),
+                "`setter(name = \"...\")`",
+            ));
+        }
+
+        if let Some(each) = &f.config.each {
+            names.push((each.clone(), "another `each` name"));
+            names.push((format_ident!("extend_{}", f.ident), "another field name"));
+        }
 
-    if !input.generics.params.is_empty() {
-        let generics = &input.generics;
+        for (name, fix) in names {
+            if reserved.iter().any(|reserved| name == reserved) {
+                errors.push(Error::new_spanned(
+                    f.field,
+                    format!(
+                        "`{}` is already a method of `{}`, use {} to rename it",
+                        name, builder_name, fix
+                    ),
+                ));
+            }
+        }
+    }
+
+    match errors.into_iter().reduce(|mut combined, err| {
+        combined.combine(err);
+        combined
+    }) {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+/// Rejects everything a `#[builder(const)]` builder cannot do in a `const fn`:
+/// calling trait methods, allocating and running user functions.
+fn check_const(
+    target: &BuilderTarget,
+    config: &BuilderConfig,
+    fields: &[BuilderField],
+) -> Result<()> {
+    let span = &target.input.ident;
+
+    if config.typestate {
         return Err(Error::new_spanned(
-            generics,
-            "Builder does not currently support generics",
+            span,
+            "`const` builders cannot be combined with `typestate`",
         ));
     }
 
-    let builder_name = Ident::new(&format!("{}Builder", name), name.span());
+    if config.pattern != BuilderPattern::Owned {
+        return Err(Error::new_spanned(
+            span,
+            "`const` builders only support the owned pattern",
+        ));
+    }
 
-    let builder_fields = fields.iter().map(|f| {
-        let field_name = &f.ident;
-        let field_type = &f.ty;
-        quote_spanned! { f.span() =>
-            #field_name: ::core::option::Option<#field_type>
+    if let Some(validate) = &config.validate {
+        return Err(Error::new_spanned(
+            validate,
+            "`validate` cannot run in a `const` builder",
+        ));
+    }
+
+    if config.conversion.is_some() {
+        return Err(Error::new_spanned(
+            span,
+            "`const` builders cannot use `into` or `try_into` setters",
+        ));
+    }
+
+    for f in fields {
+        let unsupported = if f.nested.is_some() {
+            Some("`nested`")
+        } else if f.config.each.is_some() {
+            Some("`each`")
+        } else if f.config.validate.is_some() {
+            Some("`validate`")
+        } else if f.config.conversion.is_some() {
+            Some("setter conversions")
+        } else if let Some(FieldDefault::Trait) = f.config.default {
+            Some("`default` without a const expression")
+        } else if f.config.fallible || f.config.asynchronous {
+            Some("`fallible` or `async` initializers")
+        } else {
+            None
+        };
+
+        if let Some(unsupported) = unsupported {
+            return Err(Error::new_spanned(
+                f.field,
+                format!("{} cannot be used in a `const` builder", unsupported),
+            ));
         }
-    });
+    }
 
-    let builder_methods = fields.iter().map(|f| {
-        let field_name = f.ident.as_ref().unwrap();
-        let field_type = &f.ty;
-        let field_vis = &f.vis;
-        let doc_comment = format!("Set the `{}` field.", field_name);
+    Ok(())
+}
 
-        quote_spanned! { f.span() =>
-            #[doc = #doc_comment]
-            #[inline]
-            #field_vis fn #field_name(mut self, value: #field_type) -> Self {
-                self.#field_name = ::core::option::Option::Some(value);
-                self
+/// Generates a builder that tracks set fields with `Option`s and reports
+/// missing ones from `build()` at runtime.
+fn expand_runtime_builder(
+    target: &BuilderTarget,
+    config: &BuilderConfig,
+    fields: &[BuilderField],
+) -> Result<TokenStream> {
+    let name = &target.input.ident;
+    let vis = &target.input.vis;
+    let generics = &target.input.generics;
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let builder_name = &target.builder_name;
+    let builder_fn = &target.builder_fn;
+    let builder_doc = builder_fn_doc(target);
+    let error_name = target.error_name();
+    let (marker_field, marker_init) = phantom_marker(generics);
+
+    let error_ty = match &config.error {
+        Some(error) => quote! { #error },
+        None => quote! { #error_name },
+    };
+
+    let builder_fields = fields.iter().filter(|f| f.is_stored()).map(|f| {
+        let field_name = &f.ident;
+        let value_ty = f.value_ty;
+        let serde_attr = f
+            .config
+            .serde
+            .as_ref()
+            .map(|attr| quote! { #[serde(#attr)] });
+
+        match &f.nested {
+            Some(nested) => {
+                let nested_builder = &nested.builder;
+                quote_spanned! { f.field.span() =>
+                    #serde_attr
+                    #field_name: #nested_builder
+                }
             }
+            None => quote_spanned! { f.field.span() =>
+                #serde_attr
+                #field_name: ::core::option::Option<#value_ty>
+            },
         }
     });
 
-    let build_fields = fields.iter().map(|f| {
-        let field_name = f.ident.as_ref().unwrap();
-        let field_name_str = field_name.to_string();
+    let builder_methods = fields
+        .iter()
+        .filter(|f| f.is_stored())
+        .map(|f| {
+            if let Some(nested) = &f.nested {
+                return Ok(nested_setters(f, nested, config.pattern));
+            }
+
+            if config.constant {
+                return Ok(const_setter(f));
+            }
+
+            let setter = option_setter(f, config.pattern);
+            let each = each_setters(f, config.pattern)?;
+            Ok(quote! { #setter #each })
+        })
+        .collect::<Result<Vec<_>>>()?;
 
-        quote_spanned! { f.span() =>
-            #field_name: self.#field_name.ok_or_else(|| {
-                ::std::format!("field `{}` is required but was not set", #field_name_str)
-            })?
+    let required_fields = required_check(config, fields, &error_name);
+
+    let build_receiver = config.pattern.build_receiver();
+
+    // Initializers that can fail or have to be awaited get their own build
+    // method, so `build()` always means a plain, synchronous build.
+    let (build_fn, asyncness, build_doc) = if fields.iter().any(|f| f.config.asynchronous) {
+        (
+            format_ident!("build_async"),
+            quote! { async },
+            "Build the final instance, awaiting the `default_with` initializers of unset fields and returning an error if a field is missing or an initializer fails.",
+        )
+    } else if fields.iter().any(|f| f.config.fallible) {
+        (
+            format_ident!("try_build"),
+            quote! {},
+            "Build the final instance, returning an error if a field is missing or a `default_with` initializer fails.",
+        )
+    } else {
+        (
+            format_ident!("build"),
+            quote! {},
+            "Build the final instance, returning an error listing every required field that is missing.",
+        )
+    };
+
+    let optional_fields = fields.iter().filter(|f| !f.is_required()).map(|f| {
+        let field_name = &f.ident;
+        let default = f.checked_default(config, &error_name);
+        let value = f.resolve_with(config.pattern.take(field_name), default);
+        quote_spanned! { f.field.span() =>
+            let #field_name = #value;
         }
     });
 
-    let default_fields_1 = fields.iter().map(|f| {
+    let init_failed = fields
+        .iter()
+        .any(|f| f.config.fallible || f.config.asynchronous);
+    let error_type = error_enum(vis, &target.display_name(), &error_name, init_failed);
+    let finish = finish_build(target, config, fields, &error_name);
+    let build_const = if config.constant {
+        const_build(target, fields)
+    } else {
+        quote! {}
+    };
+    let constness = if config.constant {
+        quote! { const }
+    } else {
+        quote! {}
+    };
+
+    let default_fields_1 = fields.iter().filter(|f| f.is_stored()).map(|f| {
         let field_name = &f.ident;
-        quote_spanned! { f.span() =>
-            #field_name: ::core::option::Option::None
+        if f.nested.is_some() {
+            quote_spanned! { f.field.span() =>
+                #field_name: ::core::default::Default::default()
+            }
+        } else {
+            quote_spanned! { f.field.span() =>
+                #field_name: ::core::option::Option::None
+            }
         }
     });
+    let default_fields_2 = default_fields_1.clone();
 
-    let default_fields_2 = fields.iter().map(|f| {
+    let merged_fields = fields.iter().filter(|f| f.is_stored()).map(|f| {
         let field_name = &f.ident;
-        quote_spanned! { f.span() =>
-            #field_name: ::core::option::Option::None
+        if f.nested.is_some() {
+            quote! { #field_name: self.#field_name.merge(other.#field_name) }
+        } else {
+            quote! { #field_name: other.#field_name.or(self.#field_name) }
         }
     });
+    let marker_move = if marker_init.is_empty() {
+        quote! {}
+    } else {
+        quote! { __marker: self.__marker, }
+    };
+
+    // Every stored field is an `Option` or a nested builder, so a
+    // `#[serde(default)]` builder can be deserialized from a partial config.
+    let (serde_derive, marker_field) = if config.serde {
+        let marker_field = if marker_field.is_empty() {
+            marker_field
+        } else {
+            quote! { #[serde(skip)] #marker_field }
+        };
+
+        (
+            quote! {
+                #[derive(::serde::Deserialize)]
+                #[serde(default)]
+            },
+            marker_field,
+        )
+    } else {
+        (quote! {}, marker_field)
+    };
+
+    let conversions = builder_conversions(
+        target,
+        fields,
+        quote! { #builder_name #ty_generics },
+        |f, value| {
+            if f.nested.is_some() {
+                quote! { ::core::convert::From::from(#value) }
+            } else if f.optional {
+                value
+            } else {
+                quote! { ::core::option::Option::Some(#value) }
+            }
+        },
+    );
 
     Ok(quote! {
         #[derive(Clone, Debug)]
-        #vis struct #builder_name {
+        #serde_derive
+        #vis struct #builder_name #generics #where_clause {
             #(#builder_fields,)*
+            #marker_field
         }
 
-        impl #builder_name {
+        impl #impl_generics #builder_name #ty_generics #where_clause {
             #(#builder_methods)*
 
-            #[doc = "Build the final instance, returning an error if any required fields are missing."]
-            #vis fn build(self) -> ::std::result::Result<#name, ::std::string::String> {
-                ::std::result::Result::Ok(#name {
-                    #(#build_fields,)*
-                })
+            #[doc = "Overlay the fields set on `other` onto this builder, keeping this builder's value for fields `other` leaves unset."]
+            #vis fn merge(self, other: Self) -> Self {
+                Self {
+                    #(#merged_fields,)*
+                    #marker_move
+                }
+            }
+
+            #[doc = #build_doc]
+            #vis #asyncness fn #build_fn(#build_receiver) -> ::std::result::Result<#name #ty_generics, #error_ty> {
+                #required_fields
+                #(#optional_fields)*
+                #finish
             }
+
+            #build_const
         }
 
-        impl #name {
-            #[doc = "Create a new builder instance."]
+        impl #impl_generics #name #ty_generics #where_clause {
+            #[doc = #builder_doc]
             #[inline]
-            #vis fn builder() -> #builder_name {
+            #vis #constness fn #builder_fn() -> #builder_name #ty_generics {
                 #builder_name {
                     #(#default_fields_1,)*
+                    #marker_init
                 }
             }
         }
 
-        impl ::core::default::Default for #builder_name {
+        impl #impl_generics ::core::default::Default for #builder_name #ty_generics #where_clause {
             fn default() -> Self {
                 Self {
                     #(#default_fields_2,)*
+                    #marker_init
                 }
             }
         }
+
+        #conversions
+        #error_type
     })
 }
+
+/// Runs the field and struct-level `validate` hooks on the resolved field
+/// values, then constructs the struct and returns it wrapped in `Ok`.
+fn finish_build(
+    target: &BuilderTarget,
+    config: &BuilderConfig,
+    fields: &[BuilderField],
+    error_name: &Ident,
+) -> TokenStream {
+    let path = &target.path;
+    let field_inits = fields.iter().map(|f| f.init());
+
+    let field_checks = fields.iter().filter_map(|f| {
+        let validate = f.config.validate.as_ref()?;
+        let field_name = &f.ident;
+        let field_name_str = field_name.to_string();
+        let error = config.convert_error(quote! {
+            #error_name::ValidationFailed {
+                field: ::std::string::String::from(#field_name_str),
+                message: ::std::string::ToString::to_string(&err),
+            }
+        });
+
+        Some(quote! {
+            if let ::std::result::Result::Err(err) = #validate(&#field_name) {
+                return ::std::result::Result::Err(#error);
+            }
+        })
+    });
+
+    let built = quote! {
+        #path {
+            #(#field_inits,)*
+        }
+    };
+
+    match &config.validate {
+        Some(validate) => {
+            let error = config.convert_error(quote! {
+                #error_name::Invalid(::std::string::ToString::to_string(&err))
+            });
+
+            quote! {
+                #(#field_checks)*
+
+                let built = #built;
+                if let ::std::result::Result::Err(err) = #validate(&built) {
+                    return ::std::result::Result::Err(#error);
+                }
+
+                ::std::result::Result::Ok(built)
+            }
+        }
+        None => quote! {
+            #(#field_checks)*
+
+            ::std::result::Result::Ok(#built)
+        },
+    }
+}
+
+fn has_validators(config: &BuilderConfig, fields: &[BuilderField]) -> bool {
+    config.validate.is_some() || fields.iter().any(|f| f.config.validate.is_some())
+}
+
+/// Moves every required field out of the builder and builds every nested
+/// one, or returns a `MissingFields` error naming all of the fields that were
+/// never set, including those missing from nested builders as `outer.inner`.
+fn required_check(
+    config: &BuilderConfig,
+    fields: &[BuilderField],
+    error_name: &Ident,
+) -> TokenStream {
+    let required: Vec<&BuilderField> = fields.iter().filter(|f| f.is_required()).collect();
+
+    if required.is_empty() {
+        return quote! {};
+    }
+
+    let names: Vec<&Ident> = required.iter().map(|f| &f.ident).collect();
+    let error = config.convert_error(quote! { #error_name::MissingFields(__missing) });
+
+    let nested_builds = required.iter().filter_map(|f| {
+        let nested = f.nested.as_ref()?;
+        let field_name = &f.ident;
+        let field_name_str = field_name.to_string();
+        let missing = format_ident!("__{}_missing", field_name);
+        let stored = config.pattern.take(field_name);
+        let child_error = &nested.error;
+
+        let invalid_field = config.convert_error(quote! {
+            #error_name::ValidationFailed {
+                field: ::std::format!("{}.{}", #field_name_str, field),
+                message,
+            }
+        });
+        // `fallible` and `async` name the nested builder's own build method,
+        // the only ones that can report `InitFailed`.
+        let (build, init_failed) = if f.config.fallible || f.config.asynchronous {
+            let error = config.convert_error(quote! {
+                #error_name::InitFailed {
+                    field: ::std::format!("{}.{}", #field_name_str, field),
+                    message,
+                }
+            });
+            let build = if f.config.asynchronous {
+                quote_spanned! { f.field.ty.span() => build_async().await }
+            } else {
+                quote_spanned! { f.field.ty.span() => try_build() }
+            };
+
+            (
+                build,
+                quote! {
+                    ::std::result::Result::Err(#child_error::InitFailed { field, message }) => {
+                        return ::std::result::Result::Err(#error);
+                    }
+                },
+            )
+        } else {
+            (quote_spanned! { f.field.ty.span() => build() }, quote! {})
+        };
+        let invalid_struct = config.convert_error(quote! {
+            #error_name::ValidationFailed {
+                field: ::std::string::String::from(#field_name_str),
+                message,
+            }
+        });
+
+        // Spanned to the field so that a nested type whose build method
+        // doesn't return its generated error type is reported there.
+        let built = format_ident!("__{}_built", field_name);
+        let build_call = quote_spanned! { f.field.ty.span() =>
+            let #built: ::std::result::Result<_, #child_error> = #stored.#build;
+        };
+
+        Some(quote! {
+            #build_call
+            let (#field_name, #missing) = match #built {
+                ::std::result::Result::Ok(value) => {
+                    (::core::option::Option::Some(value), ::std::vec::Vec::new())
+                }
+                ::std::result::Result::Err(#child_error::MissingFields(fields)) => {
+                    (::core::option::Option::None, fields)
+                }
+                ::std::result::Result::Err(#child_error::ValidationFailed { field, message }) => {
+                    return ::std::result::Result::Err(#invalid_field);
+                }
+                ::std::result::Result::Err(#child_error::Invalid(message)) => {
+                    return ::std::result::Result::Err(#invalid_struct);
+                }
+                #init_failed
+            };
+        })
+    });
+
+    let stored = required.iter().map(|f| {
+        let field_name = &f.ident;
+        if f.nested.is_some() {
+            quote! { #field_name }
+        } else {
+            config.pattern.take(field_name)
+        }
+    });
+
+    let fallback = required.iter().map(|f| {
+        let field_name = &f.ident;
+        if f.nested.is_some() {
+            quote! { _ }
+        } else {
+            quote! { #field_name }
+        }
+    });
+
+    let collect_missing = required.iter().map(|f| {
+        let field_name = &f.ident;
+        let field_name_str = field_name.to_string();
+
+        if f.nested.is_some() {
+            let missing = format_ident!("__{}_missing", field_name);
+            quote! {
+                __missing.extend(
+                    #missing
+                        .into_iter()
+                        .map(|field| ::std::format!("{}.{}", #field_name_str, field)),
+                );
+            }
+        } else {
+            quote! {
+                if #field_name.is_none() {
+                    __missing.push(::std::string::String::from(#field_name_str));
+                }
+            }
+        }
+    });
+
+    quote! {
+        #(#nested_builds)*
+
+        let (#(#names,)*) = match (#(#stored,)*) {
+            (#(::core::option::Option::Some(#names),)*) => (#(#names,)*),
+            (#(#fallback,)*) => {
+                let mut __missing = ::std::vec::Vec::new();
+                #(#collect_missing)*
+
+                return ::std::result::Result::Err(#error);
+            }
+        };
+    }
+}
+
+/// The `FooBuilderError` enum returned by `build()`, or converted into the
+/// type named by `#[builder(error = ...)]`. `InitFailed` only exists for
+/// builders with `fallible` or `async` fields, the only ones that can report it.
+fn error_enum(
+    vis: &syn::Visibility,
+    name: &str,
+    error_name: &Ident,
+    init_failed: bool,
+) -> TokenStream {
+    let doc_comment = format!("Error returned when building a `{}` fails.", name);
+    let (init_failed_variant, init_failed_arm) = if init_failed {
+        (
+            quote! {
+                #[doc = "A `default_with` initializer of an unset field failed."]
+                InitFailed {
+                    field: ::std::string::String,
+                    message: ::std::string::String,
+                },
+            },
+            quote! {
+                Self::InitFailed { field, message } => {
+                    ::core::write!(f, "failed to initialize field `{}`: {}", field, message)
+                }
+            },
+        )
+    } else {
+        (quote! {}, quote! {})
+    };
+
+    quote! {
+        #[doc = #doc_comment]
+        #[derive(Clone, Debug, PartialEq, Eq)]
+        #vis enum #error_name {
+            #[doc = "One or more required fields were never set."]
+            MissingFields(::std::vec::Vec<::std::string::String>),
+            #[doc = "A field was set to a value that failed validation."]
+            ValidationFailed {
+                field: ::std::string::String,
+                message: ::std::string::String,
+            },
+            #[doc = "The struct as a whole failed validation."]
+            Invalid(::std::string::String),
+            #init_failed_variant
+        }
+
+        impl ::core::fmt::Display for #error_name {
+            fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                match self {
+                    Self::MissingFields(fields) if fields.len() == 1 => {
+                        ::core::write!(f, "field `{}` is required but was not set", fields[0])
+                    }
+                    Self::MissingFields(fields) => {
+                        f.write_str("fields ")?;
+                        for (idx, field) in fields.iter().enumerate() {
+                            if idx > 0 {
+                                f.write_str(", ")?;
+                            }
+                            ::core::write!(f, "`{}`", field)?;
+                        }
+                        f.write_str(" are required but were not set")
+                    }
+                    Self::ValidationFailed { field, message } => {
+                        ::core::write!(f, "invalid value for field `{}`: {}", field, message)
+                    }
+                    Self::Invalid(message) => {
+                        ::core::write!(f, "invalid `{}`: {}", #name, message)
+                    }
+                    #init_failed_arm
+                }
+            }
+        }
+
+        impl ::std::error::Error for #error_name {}
+    }
+}
+
+/// Generates a builder whose type parameters record which required fields
+/// have been set, so `build()` only exists once all of them are.
+///
+/// Every required field gets a state parameter that is `()` while unset and
+/// `(T,)` once its setter has been called. Optional fields are stored as
+/// `Option`s and can be set in any state.
+fn expand_typestate_builder(
+    target: &BuilderTarget,
+    config: &BuilderConfig,
+    fields: &[BuilderField],
+) -> Result<TokenStream> {
+    let name = &target.input.ident;
+    let vis = &target.input.vis;
+    let generics = &target.input.generics;
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let builder_name = &target.builder_name;
+    let builder_fn = &target.builder_fn;
+    let builder_doc = builder_fn_doc(target);
+    let (marker_field, marker_init) = phantom_marker(generics);
+
+    let required: Vec<&BuilderField> = fields.iter().filter(|f| f.is_required()).collect();
+    let states: Vec<Ident> = required
+        .iter()
+        .enumerate()
+        .map(|(idx, f)| state_param(idx, &f.ident))
+        .collect();
+    let type_args = generic_args(generics);
+
+    let mut decl_generics = generics.clone();
+    for state in &states {
+        decl_generics
+            .params
+            .push(GenericParam::Type(parse_quote!(#state = ())));
+    }
+
+    let mut any_state_generics = generics.clone();
+    for state in &states {
+        any_state_generics
+            .params
+            .push(GenericParam::Type(parse_quote!(#state)));
+    }
+    let (any_state_impl_generics, _, _) = any_state_generics.split_for_impl();
+
+    let builder_fields = fields.iter().filter(|f| f.is_stored()).map(|f| {
+        let field_name = &f.ident;
+        let stored_ty = match required.iter().position(|r| r.ident == f.ident) {
+            Some(idx) => {
+                let state = &states[idx];
+                quote! { #state }
+            }
+            None => {
+                let value_ty = f.value_ty;
+                quote! { ::core::option::Option<#value_ty> }
+            }
+        };
+
+        quote_spanned! { f.field.span() =>
+            #field_name: #stored_ty
+        }
+    });
+
+    let marker_move = if marker_init.is_empty() {
+        quote! {}
+    } else {
+        quote! { __marker: self.__marker, }
+    };
+
+    let required_setters = required.iter().enumerate().map(|(idx, f)| {
+        let field_name = &f.ident;
+        let field_type = f.value_ty;
+        let setter_name = f.setter_name();
+        let setter_vis = f.setter_vis();
+        let docs = f.setter_docs();
+        let input = f.setter_input();
+        let SetterInput {
+            generics: setter_fn_generics,
+            params,
+            convert,
+            ..
+        } = &input;
+
+        let mut setter_generics = generics.clone();
+        for (other, state) in states.iter().enumerate() {
+            if other != idx {
+                setter_generics
+                    .params
+                    .push(GenericParam::Type(parse_quote!(#state)));
+            }
+        }
+        let (setter_impl_generics, _, _) = setter_generics.split_for_impl();
+
+        let state_args = |set: TokenStream| {
+            states.iter().enumerate().map(move |(other, state)| {
+                if other == idx {
+                    set.clone()
+                } else {
+                    quote! { #state }
+                }
+            })
+        };
+        let before = state_args(quote! { () });
+        let after = state_args(quote! { (#field_type,) });
+
+        let moved_fields = fields.iter().filter(|f| f.is_stored()).map(|other| {
+            let other_name = &other.ident;
+            if other_name == field_name {
+                quote! { #other_name: (value,) }
+            } else {
+                quote! { #other_name: self.#other_name }
+            }
+        });
+
+        let return_ty = input.return_ty(quote! { #builder_name<#(#type_args,)* #(#after),*> });
+        let finish = input.finish(quote! {
+            #builder_name {
+                #(#moved_fields,)*
+                #marker_move
+            }
+        });
+
+        quote_spanned! { f.field.span() =>
+            impl #setter_impl_generics #builder_name<#(#type_args,)* #(#before),*> #where_clause {
+                #docs
+                #[inline]
+                #setter_vis fn #setter_name #setter_fn_generics(self, #params) -> #return_ty {
+                    #convert
+                    #finish
+                }
+            }
+        }
+    });
+
+    let optional_setters = fields
+        .iter()
+        .filter(|f| f.is_stored() && !f.is_required())
+        .map(|f| {
+            let setter = option_setter(f, config.pattern);
+            let each = each_setters(f, config.pattern)?;
+            Ok(quote! { #setter #each })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let set_states = required.iter().map(|f| {
+        let field_type = f.value_ty;
+        quote! { (#field_type,) }
+    });
+    let set_states_2 = set_states.clone();
+    let unset_states = required.iter().map(|_| quote! { () });
+    let unset_states_2 = required.iter().map(|_| quote! { () });
+
+    let build_fields = fields.iter().map(|f| {
+        let field_name = &f.ident;
+
+        if f.is_required() {
+            quote! { let #field_name = self.#field_name.0; }
+        } else {
+            let value = f.resolve_optional(quote! { self.#field_name });
+            quote_spanned! { f.field.span() =>
+                let #field_name = #value;
+            }
+        }
+    });
+
+    let conversions = builder_conversions(
+        target,
+        fields,
+        quote! { #builder_name<#(#type_args,)* #(#set_states_2),*> },
+        |f, value| {
+            if f.is_required() {
+                quote! { (#value,) }
+            } else if f.optional {
+                value
+            } else {
+                quote! { ::core::option::Option::Some(#value) }
+            }
+        },
+    );
+
+    let build_fn = if has_validators(config, fields) {
+        let error_name = target.error_name();
+        let error_ty = match &config.error {
+            Some(error) => quote! { #error },
+            None => quote! { #error_name },
+        };
+        let finish = finish_build(target, config, fields, &error_name);
+        let error_type = error_enum(vis, &target.display_name(), &error_name, false);
+
+        quote! {
+            impl #impl_generics #builder_name<#(#type_args,)* #(#set_states),*> #where_clause {
+                #[doc = "Build the final instance, returning an error if a `validate` hook rejects it. Only available once every required field is set."]
+                #vis fn build(self) -> ::std::result::Result<#name #ty_generics, #error_ty> {
+                    #(#build_fields)*
+                    #finish
+                }
+            }
+
+            #error_type
+        }
+    } else {
+        let path = &target.path;
+        let field_inits = fields.iter().map(|f| f.init());
+
+        quote! {
+            impl #impl_generics #builder_name<#(#type_args,)* #(#set_states),*> #where_clause {
+                #[doc = "Build the final instance. Only available once every required field is set."]
+                #[inline]
+                #vis fn build(self) -> #name #ty_generics {
+                    #(#build_fields)*
+
+                    #path {
+                        #(#field_inits,)*
+                    }
+                }
+            }
+        }
+    };
+
+    let default_fields_1 = fields.iter().filter(|f| f.is_stored()).map(|f| {
+        let field_name = &f.ident;
+        if f.is_required() {
+            quote! { #field_name: () }
+        } else {
+            quote! { #field_name: ::core::option::Option::None }
+        }
+    });
+    let default_fields_2 = default_fields_1.clone();
+
+    Ok(quote! {
+        #[derive(Clone, Debug)]
+        #vis struct #builder_name #decl_generics #where_clause {
+            #(#builder_fields,)*
+            #marker_field
+        }
+
+        #(#required_setters)*
+
+        impl #any_state_impl_generics #builder_name<#(#type_args,)* #(#states),*> #where_clause {
+            #(#optional_setters)*
+        }
+
+        #build_fn
+
+        impl #impl_generics #name #ty_generics #where_clause {
+            #[doc = #builder_doc]
+            #[inline]
+            #vis fn #builder_fn() -> #builder_name<#(#type_args,)* #(#unset_states),*> {
+                #builder_name {
+                    #(#default_fields_1,)*
+                    #marker_init
+                }
+            }
+        }
+
+        impl #impl_generics ::core::default::Default for #builder_name<#(#type_args,)* #(#unset_states_2),*> #where_clause {
+            fn default() -> Self {
+                Self {
+                    #(#default_fields_2,)*
+                    #marker_init
+                }
+            }
+        }
+
+        #conversions
+    })
+}
+
+/// `impl From<Foo> for FooBuilder` and `Foo::to_builder()`, which yield a
+/// builder with every field set. `populate` wraps a field's value the way the
+/// builder stores it. Enum variant builders get neither, since the enum may
+/// hold another variant.
+///
+/// `to_builder()` is only callable when every field type is `Clone`; the bounds
+/// go through a higher-ranked lifetime so non-`Clone` fields make the method
+/// unavailable rather than the derive fail.
+fn builder_conversions(
+    target: &BuilderTarget,
+    fields: &[BuilderField],
+    builder_ty: TokenStream,
+    populate: impl Fn(&BuilderField, TokenStream) -> TokenStream,
+) -> TokenStream {
+    if target.variant.is_some() {
+        return quote! {};
+    }
+
+    let input = target.input;
+    let builder_name = &target.builder_name;
+    let name = &input.ident;
+    let vis = &input.vis;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let (_, marker_init) = phantom_marker(&input.generics);
+
+    let stored: Vec<&BuilderField> = fields.iter().filter(|f| f.is_stored()).collect();
+
+    let from_fields = stored.iter().map(|f| {
+        let field_name = &f.ident;
+        let member = &f.member;
+        let value = populate(f, quote! { value.#member });
+        quote! { #field_name: #value }
+    });
+    let cloned_fields = stored.iter().map(|f| {
+        let field_name = &f.ident;
+        let member = &f.member;
+        let value = populate(f, quote! { ::core::clone::Clone::clone(&self.#member) });
+        quote! { #field_name: #value }
+    });
+    let field_types = stored.iter().map(|f| &f.field.ty);
+
+    quote! {
+        impl #impl_generics ::core::convert::From<#name #ty_generics> for #builder_ty #where_clause {
+            #[allow(unused_variables)]
+            fn from(value: #name #ty_generics) -> Self {
+                Self {
+                    #(#from_fields,)*
+                    #marker_init
+                }
+            }
+        }
+
+        impl #impl_generics #name #ty_generics #where_clause {
+            #[doc = "Create a builder with every field set to a clone of this instance's."]
+            #[inline]
+            #vis fn to_builder(&self) -> #builder_ty
+            where
+                #(for<'__builder> #field_types: ::core::clone::Clone,)*
+            {
+                #builder_name {
+                    #(#cloned_fields,)*
+                    #marker_init
+                }
+            }
+        }
+    }
+}
+
+/// A consuming setter that stores `value` in the field's `Option` slot.
+fn option_setter(f: &BuilderField, pattern: BuilderPattern) -> TokenStream {
+    let field_name = &f.ident;
+    let setter_name = f.setter_name();
+    let setter_vis = f.setter_vis();
+    let docs = f.setter_docs();
+    let input = f.setter_input();
+    let SetterInput {
+        generics,
+        params,
+        convert,
+        ..
+    } = &input;
+    let receiver = pattern.receiver();
+    let (target, prologue) = pattern.target();
+    let return_ty = input.return_ty(pattern.return_ty());
+    let finish = input.finish(target.clone());
+
+    quote_spanned! { f.field.span() =>
+        #docs
+        #[inline]
+        #setter_vis fn #setter_name #generics(#receiver, #params) -> #return_ty {
+            #convert
+            #prologue
+            #target.#field_name = ::core::option::Option::Some(value);
+            #finish
+        }
+    }
+}
+
+/// A `const fn` setter for `#[builder(const)]` builders.
+///
+/// Assigning would drop the previous value, which a `const fn` cannot do for
+/// types with destructors, so the previous value is forgotten instead. Setting
+/// such a field twice at runtime leaks the first value.
+fn const_setter(f: &BuilderField) -> TokenStream {
+    let field_name = &f.ident;
+    let field_type = f.value_ty;
+    let setter_name = f.setter_name();
+    let setter_vis = f.setter_vis();
+    let docs = f.setter_docs();
+
+    quote_spanned! { f.field.span() =>
+        #docs
+        #[inline]
+        #[allow(forgetting_copy_types, clippy::forget_non_drop)]
+        #setter_vis const fn #setter_name(mut self, value: #field_type) -> Self {
+            ::core::mem::forget(self.#field_name.replace(value));
+            self
+        }
+    }
+}
+
+/// `build_const()` for `#[builder(const)]` builders, which panics on a
+/// missing field so that const evaluation fails at compile time.
+///
+/// Every field is `take`n out of the builder and the emptied builder is
+/// forgotten, since a `const fn` cannot drop values that may have destructors.
+fn const_build(target: &BuilderTarget, fields: &[BuilderField]) -> TokenStream {
+    let vis = &target.input.vis;
+    let name = &target.input.ident;
+    let (_, ty_generics, _) = target.input.generics.split_for_impl();
+    let path = &target.path;
+
+    let resolved = fields.iter().map(|f| {
+        let field_name = &f.ident;
+        let default = f.default_value();
+
+        let value = if !f.is_stored() {
+            default
+        } else if f.is_required() {
+            let message = format!("field `{}` is required but was not set", field_name);
+            return quote! {
+                if self.#field_name.is_none() {
+                    ::core::panic!(#message);
+                }
+                let #field_name = self.#field_name.take().unwrap();
+            };
+        } else if f.optional && f.config.default.is_none() {
+            quote! { self.#field_name.take() }
+        } else if f.optional {
+            quote! {
+                if self.#field_name.is_some() {
+                    self.#field_name.take()
+                } else {
+                    #default
+                }
+            }
+        } else {
+            quote! {
+                if self.#field_name.is_some() {
+                    self.#field_name.take().unwrap()
+                } else {
+                    #default
+                }
+            }
+        };
+
+        quote! { let #field_name = #value; }
+    });
+    let field_inits = fields.iter().map(|f| f.init());
+
+    quote! {
+        #[doc = "Build the final instance in a const context, panicking if a required field is missing."]
+        #[allow(clippy::forget_non_drop)]
+        #vis const fn build_const(mut self) -> #name #ty_generics {
+            #(#resolved)*
+            ::core::mem::forget(self);
+
+            #path {
+                #(#field_inits,)*
+            }
+        }
+    }
+}
+
+/// The accessors of a `#[builder(nested)]` field: `field()` borrows the
+/// nested builder and `field_with(f)` replaces it with `f(builder)`. Only
+/// `field_with` works with a nested builder that has owned setters, since
+/// those can't be called through `&mut`.
+fn nested_setters(
+    f: &BuilderField,
+    nested: &NestedBuilder,
+    pattern: BuilderPattern,
+) -> TokenStream {
+    let field_name = &f.ident;
+    let setter_name = f.setter_name();
+    let setter_vis = f.setter_vis();
+    let nested_builder = &nested.builder;
+    let with_name = format_ident!("{}_with", setter_name);
+    let doc_comment = format!("Access the builder of the `{}` field.", field_name);
+    let with_doc = format!(
+        "Update the builder of the `{}` field with `update`.",
+        field_name
+    );
+    let receiver = pattern.receiver();
+    let return_ty = pattern.return_ty();
+    let (target, prologue) = pattern.target();
+
+    quote_spanned! { f.field.span() =>
+        #[doc = #doc_comment]
+        #[inline]
+        #setter_vis fn #setter_name(&mut self) -> &mut #nested_builder {
+            &mut self.#field_name
+        }
+
+        #[doc = #with_doc]
+        #[inline]
+        #setter_vis fn #with_name(
+            #receiver,
+            update: impl ::core::ops::FnOnce(#nested_builder) -> #nested_builder,
+        ) -> #return_ty {
+            #prologue
+            let builder = ::core::mem::take(&mut #target.#field_name);
+            #target.#field_name = update(builder);
+            #target
+        }
+    }
+}
+
+/// The `#[builder(each = "item")]` methods of a collection field: `item(x)`
+/// adds one element (or one `key, value` entry) and `extend_field(iter)` adds
+/// many, both starting from an empty collection.
+fn each_setters(f: &BuilderField, pattern: BuilderPattern) -> Result<TokenStream> {
+    let Some(each) = &f.config.each else {
+        return Ok(quote! {});
+    };
+
+    let field_name = &f.ident;
+    let field_vis = f.setter_vis();
+    let extend_name = format_ident!("extend_{}", field_name);
+    let into = matches!(f.config.conversion, Some(SetterConversion::Into));
+
+    let param_ty = |ty: &Type| {
+        if into {
+            quote! { impl ::core::convert::Into<#ty> }
+        } else {
+            quote! { #ty }
+        }
+    };
+    let convert = |name: &Ident| {
+        if into {
+            quote! { ::core::convert::Into::into(#name) }
+        } else {
+            quote! { #name }
+        }
+    };
+
+    let (params, item, item_ty) = match collection_item(f.value_ty) {
+        Some(CollectionItem::Single(ty)) => {
+            let item_param = param_ty(ty);
+            let item = convert(&format_ident!("item"));
+            (quote! { item: #item_param }, item, quote! { #ty })
+        }
+        Some(CollectionItem::Entry(key_ty, value_ty)) => {
+            let key_param = param_ty(key_ty);
+            let value_param = param_ty(value_ty);
+            let key = convert(&format_ident!("key"));
+            let value = convert(&format_ident!("value"));
+            (
+                quote! { key: #key_param, value: #value_param },
+                quote! { (#key, #value) },
+                quote! { (#key_ty, #value_ty) },
+            )
+        }
+        None => {
+            return Err(Error::new_spanned(
+                &f.field.ty,
+                "`each` requires a collection type such as `Vec<T>` or `HashMap<K, V>`",
+            ));
+        }
+    };
+
+    let item_doc = format!("Add one item to the `{}` field.", field_name);
+    let extend_doc = format!("Add every item of `items` to the `{}` field.", field_name);
+    let receiver = pattern.receiver();
+    let return_ty = pattern.return_ty();
+    let (target, prologue) = pattern.target();
+
+    Ok(quote_spanned! { f.field.span() =>
+        #[doc = #item_doc]
+        #[inline]
+        #field_vis fn #each(#receiver, #params) -> #return_ty {
+            #prologue
+            ::core::iter::Extend::extend(
+                #target.#field_name.get_or_insert_with(::core::default::Default::default),
+                ::core::iter::once(#item),
+            );
+            #target
+        }
+
+        #[doc = #extend_doc]
+        #[inline]
+        #field_vis fn #extend_name<I: ::core::iter::IntoIterator<Item = #item_ty>>(#receiver, items: I) -> #return_ty {
+            #prologue
+            ::core::iter::Extend::extend(
+                #target.#field_name.get_or_insert_with(::core::default::Default::default),
+                items,
+            );
+            #target
+        }
+    })
+}
+
+pub(crate) fn parse_builder_config(attrs: &[syn::Attribute]) -> Result<BuilderConfig> {
+    let mut config = BuilderConfig {
+        typestate: false,
+        pattern: BuilderPattern::Owned,
+        error: None,
+        validate: None,
+        conversion: None,
+        constant: false,
+        serde: false,
+    };
+
+    for attr in attrs {
+        if !attr.path().is_ident("builder") {
+            continue;
+        }
+
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("const") {
+                if config.constant {
+                    return Err(meta.error("duplicate `const` attribute"));
+                }
+
+                config.constant = true;
+                Ok(())
+            } else if meta.path.is_ident("serde") {
+                if config.serde {
+                    return Err(meta.error("duplicate `serde` attribute"));
+                }
+
+                if !cfg!(feature = "serde") {
+                    return Err(meta.error(
+                        "`serde` needs the `serde` feature of macroni-n-cheese to be enabled",
+                    ));
+                }
+
+                config.serde = true;
+                Ok(())
+            } else if meta.path.is_ident("typestate") {
+                if config.typestate {
+                    return Err(meta.error("duplicate `typestate` attribute"));
+                }
+
+                config.typestate = true;
+                Ok(())
+            } else if meta.path.is_ident("error") {
+                if config.error.is_some() {
+                    return Err(meta.error("duplicate `error` attribute"));
+                }
+
+                config.error = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("validate") {
+                if config.validate.is_some() {
+                    return Err(meta.error("duplicate `validate` attribute"));
+                }
+
+                config.validate = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("into") || meta.path.is_ident("try_into") {
+                if config.conversion.is_some() {
+                    return Err(meta.error("only one of `into` and `try_into` can be used"));
+                }
+
+                config.conversion = Some(if meta.path.is_ident("into") {
+                    SetterConversion::Into
+                } else {
+                    SetterConversion::TryInto
+                });
+                Ok(())
+            } else if meta.path.is_ident("pattern") {
+                let s: syn::LitStr = meta.value()?.parse()?;
+                config.pattern = match s.value().as_str() {
+                    "owned" => BuilderPattern::Owned,
+                    "mutable" => BuilderPattern::Mutable,
+                    "immutable" => BuilderPattern::Immutable,
+                    _ => {
+                        return Err(Error::new_spanned(
+                            s,
+                            "expected `\"owned\"`, `\"mutable\"` or `\"immutable\"`",
+                        ));
+                    }
+                };
+                Ok(())
+            } else if meta.path.is_ident("with") {
+                Err(meta.error("`with` can only be used on fields"))
+            } else {
+                Err(meta.error(format!(
+                    "unknown builder attribute `{}`. \
+                            Valid attributes are: `typestate`, `error = ...`, `validate = ...`, \
+                            `into`, `try_into`, `pattern = \"...\"`, `const`, `serde`",
+                    meta.path
+                        .get_ident()
+                        .map(|i| i.to_string())
+                        .unwrap_or_default()
+                )))
+            }
+        })?;
+    }
+
+    Ok(config)
+}
+
+pub(crate) fn parse_field_attrs(attrs: &[syn::Attribute]) -> Result<FieldConfig> {
+    let mut config = FieldConfig {
+        default: None,
+        validate: None,
+        conversion: None,
+        each: None,
+        nested: false,
+        skip: false,
+        setter: SetterConfig::default(),
+        name: None,
+        fallible: false,
+        asynchronous: false,
+        serde: None,
+    };
+    let mut skip_attr = None;
+    let mut initializer_attr = None;
+
+    for attr in attrs {
+        if !attr.path().is_ident("builder") {
+            continue;
+        }
+
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("default") {
+                if config.default.is_some() {
+                    return Err(meta.error("only one of `default` and `default_with` can be used"));
+                }
+
+                config.default = if meta.input.peek(Token![=]) {
+                    Some(FieldDefault::Expr(meta.value()?.parse()?))
+                } else {
+                    Some(FieldDefault::Trait)
+                };
+                Ok(())
+            } else if meta.path.is_ident("validate") {
+                if config.validate.is_some() {
+                    return Err(meta.error("duplicate `validate` attribute"));
+                }
+
+                config.validate = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("into")
+                || meta.path.is_ident("try_into")
+                || meta.path.is_ident("with")
+            {
+                if config.conversion.is_some() {
+                    return Err(meta.error("only one of `into`, `try_into` and `with` can be used"));
+                }
+
+                config.conversion = Some(if meta.path.is_ident("into") {
+                    SetterConversion::Into
+                } else if meta.path.is_ident("try_into") {
+                    SetterConversion::TryInto
+                } else {
+                    let closure: ExprClosure = meta.value()?.parse()?;
+
+                    if let Some(input) = closure.inputs.iter().find(|i| !matches!(i, Pat::Type(_)))
+                    {
+                        return Err(Error::new_spanned(
+                            input,
+                            "`with` closure parameters need explicit types",
+                        ));
+                    }
+
+                    SetterConversion::With(closure)
+                });
+                Ok(())
+            } else if meta.path.is_ident("each") {
+                if config.each.is_some() {
+                    return Err(meta.error("duplicate `each` attribute"));
+                }
+
+                let s: syn::LitStr = meta.value()?.parse()?;
+                config.each = Some(s.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("nested") {
+                if config.nested {
+                    return Err(meta.error("duplicate `nested` attribute"));
+                }
+
+                config.nested = true;
+                Ok(())
+            } else if meta.path.is_ident("skip") {
+                if config.skip {
+                    return Err(meta.error("duplicate `skip` attribute"));
+                }
+
+                config.skip = true;
+                skip_attr = Some(attr);
+                Ok(())
+            } else if meta.path.is_ident("setter") {
+                parse_setter_config(&meta, &mut config.setter)
+            } else if meta.path.is_ident("default_with") {
+                if config.default.is_some() {
+                    return Err(meta.error("only one of `default` and `default_with` can be used"));
+                }
+
+                config.default = Some(FieldDefault::With(meta.value()?.parse()?));
+                Ok(())
+            } else if meta.path.is_ident("fallible") || meta.path.is_ident("async") {
+                let flag = if meta.path.is_ident("fallible") {
+                    &mut config.fallible
+                } else {
+                    &mut config.asynchronous
+                };
+
+                if *flag {
+                    return Err(meta.error(format!(
+                        "duplicate `{}` attribute",
+                        meta.path.get_ident().unwrap()
+                    )));
+                }
+
+                *flag = true;
+                initializer_attr = Some(attr);
+                Ok(())
+            } else if meta.path.is_ident("name") {
+                if config.name.is_some() {
+                    return Err(meta.error("duplicate `name` attribute"));
+                }
+
+                let s: syn::LitStr = meta.value()?.parse()?;
+                config.name = Some(s.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("serde") {
+                if config.serde.is_some() {
+                    return Err(meta.error("duplicate `serde` attribute"));
+                }
+
+                let content;
+                syn::parenthesized!(content in meta.input);
+                config.serde = Some(content.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error(format!(
+                    "unknown builder field attribute `{}`. \
+                            Valid attributes are: `default`, `default = ...`, `validate = ...`, \
+                            `into`, `try_into`, `with = |...| ...`, `each = \"...\"`, `nested`, \
+                            `skip`, `setter(...)`, `name = \"...\"`, `default_with = ...`, \
+                            `fallible`, `async`, `serde(...)`",
+                    meta.path
+                        .get_ident()
+                        .map(|i| i.to_string())
+                        .unwrap_or_default()
+                )))
+            }
+        })?;
+
+        if config.nested
+            && (config.default.is_some() || config.conversion.is_some() || config.each.is_some())
+        {
+            return Err(Error::new_spanned(
+                attr,
+                "`nested` cannot be combined with `default`, `each` or setter conversions",
+            ));
+        }
+    }
+
+    if let Some(attr) = initializer_attr
+        && !config.nested
+        && !matches!(config.default, Some(FieldDefault::With(_)))
+    {
+        return Err(Error::new_spanned(
+            attr,
+            "`fallible` and `async` only apply to `default_with = ...` initializers \
+             and `nested` fields",
+        ));
+    }
+
+    if let Some(attr) = skip_attr {
+        if config.default.is_none() {
+            return Err(Error::new_spanned(
+                attr,
+                "`skip` requires `default` or `default = ...` to build the field",
+            ));
+        }
+
+        if config.nested
+            || config.each.is_some()
+            || config.conversion.is_some()
+            || config.validate.is_some()
+            || config.setter.name.is_some()
+            || config.setter.vis.is_some()
+            || config.setter.prefix.is_some()
+            || config.serde.is_some()
+        {
+            return Err(Error::new_spanned(
+                attr,
+                "`skip` fields have no setter and cannot be combined with \
+                 `nested`, `each`, `validate`, `setter(...)`, `serde(...)` or setter conversions",
+            ));
+        }
+    }
+
+    Ok(config)
+}
+
+fn parse_setter_config(meta: &syn::meta::ParseNestedMeta, setter: &mut SetterConfig) -> Result<()> {
+    meta.parse_nested_meta(|meta| {
+        if meta.path.is_ident("name") {
+            if setter.name.is_some() {
+                return Err(meta.error("duplicate `name` attribute"));
+            }
+
+            let s: syn::LitStr = meta.value()?.parse()?;
+            setter.name = Some(s.parse()?);
+        } else if meta.path.is_ident("vis") {
+            if setter.vis.is_some() {
+                return Err(meta.error("duplicate `vis` attribute"));
+            }
+
+            let s: syn::LitStr = meta.value()?.parse()?;
+            setter.vis = Some(s.parse()?);
+        } else if meta.path.is_ident("prefix") {
+            if setter.prefix.is_some() {
+                return Err(meta.error("duplicate `prefix` attribute"));
+            }
+
+            let s: syn::LitStr = meta.value()?.parse()?;
+            let prefix = s.value();
+            if syn::parse_str::<Ident>(&format!("{}x", prefix)).is_err() {
+                return Err(Error::new_spanned(s, "`prefix` must start a valid identifier"));
+            }
+            setter.prefix = Some(prefix);
+        } else {
+            return Err(meta.error(format!(
+                "unknown setter attribute `{}`. \
+                        Valid attributes are: `name = \"...\"`, `vis = \"...\"`, `prefix = \"...\"`",
+                meta.path
+                    .get_ident()
+                    .map(|i| i.to_string())
+                    .unwrap_or_default()
+            )));
+        }
+
+        if setter.name.is_some() && setter.prefix.is_some() {
+            return Err(meta.error("`name` and `prefix` cannot be used together"));
+        }
+
+        Ok(())
+    })
+}
+
+/// Doc comment for the function returning a new builder.
+fn builder_fn_doc(target: &BuilderTarget) -> String {
+    match target.variant {
+        Some(_) => format!(
+            "Create a new builder for the `{}` variant.",
+            target.display_name()
+        ),
+        None => String::from("Create a new builder instance."),
+    }
+}
+
+/// Derives `FooBuilder<..>` and `FooBuilderError` from a field of type
+/// `Foo<..>`, keeping the path prefix and generic arguments.
+fn nested_builder(ty: &Type) -> Result<NestedBuilder> {
+    let Type::Path(type_path) = ty else {
+        return Err(Error::new_spanned(
+            ty,
+            "`nested` requires a struct type that derives `Builder`",
+        ));
+    };
+
+    if type_path.qself.is_some() || option_inner(ty).is_some() {
+        return Err(Error::new_spanned(
+            ty,
+            "`nested` requires a struct type that derives `Builder`",
+        ));
+    }
+
+    let mut builder = type_path.clone();
+    let mut error = type_path.path.clone();
+
+    let last_seg = builder.path.segments.last_mut().unwrap();
+    last_seg.ident = format_ident!("{}Builder", last_seg.ident);
+
+    let last_seg = error.segments.last_mut().unwrap();
+    last_seg.ident = format_ident!("{}BuilderError", last_seg.ident);
+    last_seg.arguments = PathArguments::None;
+
+    Ok(NestedBuilder {
+        builder: Type::Path(builder),
+        error,
+    })
+}
+
+/// The element type of a collection, judged by the last path segment: types
+/// named `...Map` hold `(K, V)` entries, anything else with a type argument
+/// holds its first one.
+fn collection_item(ty: &Type) -> Option<CollectionItem<'_>> {
+    let Type::Path(type_path) = ty else {
+        return None;
+    };
+    let last_seg = type_path.path.segments.last()?;
+    let PathArguments::AngleBracketed(args) = &last_seg.arguments else {
+        return None;
+    };
+
+    let mut type_args = args.args.iter().filter_map(|arg| match arg {
+        GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    });
+
+    if last_seg.ident.to_string().ends_with("Map") {
+        Some(CollectionItem::Entry(type_args.next()?, type_args.next()?))
+    } else {
+        Some(CollectionItem::Single(type_args.next()?))
+    }
+}
+
+/// Builds a `PhantomData` field that mentions every lifetime and type parameter
+/// of the source struct, so the builder stays well-formed even when a parameter
+/// never shows up in one of its stored fields.
+///
+/// Returns the field declaration and its initializer, both empty for
+/// non-generic structs.
+fn phantom_marker(generics: &Generics) -> (TokenStream, TokenStream) {
+    let lifetimes: Vec<_> = generics.lifetimes().map(|param| &param.lifetime).collect();
+    let type_params: Vec<_> = generics.type_params().map(|param| &param.ident).collect();
+
+    if lifetimes.is_empty() && type_params.is_empty() {
+        return (quote! {}, quote! {});
+    }
+
+    (
+        quote! {
+            __marker: ::core::marker::PhantomData<fn() -> (#(&#lifetimes (),)* #(#type_params,)*)>,
+        },
+        quote! {
+            __marker: ::core::marker::PhantomData,
+        },
+    )
+}
+
+/// The struct's own generic parameters as arguments, e.g. `'a, S, N`.
+fn generic_args(generics: &Generics) -> Vec<TokenStream> {
+    generics
+        .params
+        .iter()
+        .map(|param| match param {
+            GenericParam::Lifetime(param) => {
+                let lifetime = &param.lifetime;
+                quote! { #lifetime }
+            }
+            GenericParam::Type(param) => {
+                let ident = &param.ident;
+                quote! { #ident }
+            }
+            GenericParam::Const(param) => {
+                let ident = &param.ident;
+                quote! { #ident }
+            }
+        })
+        .collect()
+}
+
+/// Name of the typestate parameter tracking the `idx`th required field, e.g.
+/// `__StoragePath0`. The index keeps fields like `a_b` and `a__b` apart.
+fn state_param(idx: usize, field: &Ident) -> Ident {
+    let camel: String = field
+        .to_string()
+        .trim_start_matches("r#")
+        .split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect();
+
+    format_ident!("__{}{}", camel, idx)
+}
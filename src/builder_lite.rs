@@ -1,33 +1,50 @@
 use {
-    proc_macro2::TokenStream,
-    quote::{quote, quote_spanned},
-    syn::{Data, DeriveInput, Error, Field, Fields, Ident, Result, Visibility, spanned::Spanned},
+    crate::util::{inherited_vis, option_inner, snake_case},
+    proc_macro2::{Span, TokenStream, TokenTree},
+    quote::{ToTokens, format_ident, quote, quote_spanned},
+    syn::{
+        Data, DeriveInput, Error, Field, Fields, GenericArgument, GenericParam, Ident, Index,
+        Member, PathArguments, Result, Type, TypeParam, Visibility, spanned::Spanned,
+    },
 };
 
 pub(crate) fn expand_builder_lite(input: DeriveInput) -> Result<TokenStream> {
     let name = &input.ident;
+    let struct_config = parse_struct_attrs(&input.attrs)?;
 
-    let fields = match &input.data {
-        Data::Struct(data) => match &data.fields {
-            Fields::Named(fields) => &fields.named,
-            Fields::Unnamed(_) => {
+    let sites: Vec<FieldSite> = match &input.data {
+        Data::Struct(data) => {
+            if let Fields::Unit = data.fields {
                 return Err(Error::new_spanned(
                     &input,
-                    "BuilderLite only supports structs with named fields",
+                    "BuilderLite cannot be derived for unit structs",
                 ));
             }
-            Fields::Unit => {
+
+            if struct_config.result {
                 return Err(Error::new_spanned(
-                    &input,
-                    "BuilderLite cannot be derived for unit structs",
+                    &input.ident,
+                    "`result` only applies to enums",
                 ));
             }
-        },
+
+            FieldSite::all(&data.fields, None)
+        }
         Data::Enum(data_enum) => {
-            return Err(Error::new_spanned(
-                data_enum.enum_token,
-                "BuilderLite cannot be derived for enums",
-            ));
+            let sites: Vec<FieldSite> = data_enum
+                .variants
+                .iter()
+                .flat_map(|variant| FieldSite::all(&variant.fields, Some(&variant.ident)))
+                .collect();
+
+            if sites.is_empty() {
+                return Err(Error::new_spanned(
+                    data_enum.enum_token,
+                    "BuilderLite needs at least one enum variant with fields",
+                ));
+            }
+
+            sites
         }
         Data::Union(data_union) => {
             return Err(Error::new_spanned(
@@ -37,25 +54,39 @@ pub(crate) fn expand_builder_lite(input: DeriveInput) -> Result<TokenStream> {
         }
     };
 
-    if !input.generics.params.is_empty() {
-        let generics = &input.generics;
-        return Err(Error::new_spanned(
-            generics,
-            "BuilderLite does not currently support generics",
-        ));
-    }
-
     let mut builder_methods = Vec::new();
+    let mut setter_visibilities = Vec::new();
+    let mut method_names: Vec<Ident> = Vec::new();
     let mut errors = Vec::new();
 
-    for field in fields {
-        match process_field(field) {
-            Ok(Some(method)) => builder_methods.push(method),
+    for site in &sites {
+        match process_field(&input, site, &struct_config) {
+            Ok(Some((method, vis, names))) => {
+                for name in names {
+                    if method_names.contains(&name) {
+                        errors.push(Error::new_spanned(
+                            site.field,
+                            format!("`{}` is already generated for another field", name),
+                        ));
+                    } else {
+                        method_names.push(name);
+                    }
+                }
+
+                builder_methods.push(method);
+                setter_visibilities.push(vis);
+            }
             Ok(None) => continue,
             Err(err) => errors.push(err),
         }
     }
 
+    if errors.is_empty()
+        && let Err(err) = validate_field_visibility(&input, &setter_visibilities)
+    {
+        errors.push(err);
+    }
+
     if !errors.is_empty() {
         let mut combined_error = errors.clone().into_iter().next().unwrap();
         for err in errors {
@@ -74,48 +105,677 @@ pub(crate) fn expand_builder_lite(input: DeriveInput) -> Result<TokenStream> {
     })
 }
 
+/// A field of the struct, or of one of the enum's variants.
+pub(crate) struct FieldSite<'a> {
+    fields: &'a Fields,
+    index: usize,
+    field: &'a Field,
+    variant: Option<&'a Ident>,
+}
+
+impl<'a> FieldSite<'a> {
+    fn all(fields: &'a Fields, variant: Option<&'a Ident>) -> Vec<Self> {
+        fields
+            .iter()
+            .enumerate()
+            .map(|(index, field)| FieldSite {
+                fields,
+                index,
+                field,
+                variant,
+            })
+            .collect()
+    }
+
+    fn member(&self) -> Member {
+        match &self.field.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(Index {
+                index: self.index as u32,
+                span: self.field.span(),
+            }),
+        }
+    }
+
+    /// The part of the method names taken from the field: `name`, `0`, or
+    /// `variant_name` for enum variants.
+    fn method_stem(&self) -> String {
+        let field = match &self.field.ident {
+            Some(ident) => ident.to_string().trim_start_matches("r#").to_owned(),
+            None => self.index.to_string(),
+        };
+
+        match self.variant {
+            Some(variant) => format!("{}_{}", snake_case(variant), field),
+            None => field,
+        }
+    }
+
+    /// Name of the field in docs, `Variant::field` for enum variants.
+    fn display_name(&self) -> String {
+        let member = self.member();
+        let field = quote! { #member }.to_string();
+
+        match self.variant {
+            Some(variant) => format!("{}::{}", variant, field),
+            None => field,
+        }
+    }
+}
+
+pub(crate) struct StructConfig {
+    prefix: String,
+    vis: Option<Visibility>,
+    set: bool,
+    map: bool,
+    /// Give every `Option` field a method resetting it to `None`.
+    without: bool,
+    /// Enum variant methods return `Result<Self, Self>` instead of ignoring
+    /// other variants.
+    result: bool,
+}
+
 pub(crate) struct FieldConfig {
     skip: bool,
     custom_name: Option<String>,
+    into: bool,
+    strip_option: bool,
+    vis: Option<Visibility>,
+    /// Also generate `set_x(&mut self, value) -> &mut Self`.
+    set: bool,
+    /// Also generate `map_x(self, f) -> Self`.
+    map: bool,
+    /// Also generate a method adding one item to the collection.
+    collection: Option<CollectionHelper>,
+    /// `#[builder(without)]`: also generate a method resetting the field to
+    /// `None`, with an optional method name.
+    without: Option<Option<String>>,
 }
 
-pub(crate) fn process_field(field: &Field) -> Result<Option<TokenStream>> {
-    let field_name = field.ident.as_ref().unwrap();
-    let field_type = &field.ty;
-    let field_vis = &field.vis;
+/// `#[builder(push)]` or `#[builder(insert)]`, with an optional method name.
+pub(crate) enum CollectionHelper {
+    Push(Option<String>),
+    Insert(Option<String>),
+}
+
+pub(crate) fn process_field(
+    input: &DeriveInput,
+    site: &FieldSite,
+    struct_config: &StructConfig,
+) -> Result<Option<(TokenStream, Visibility, Vec<Ident>)>> {
+    let field = site.field;
+    let span = field.span();
+    let member = site.member();
+    let stem = site.method_stem();
+    let display_name = site.display_name();
     let cfg = parse_builder_attrs(&field.attrs)?;
 
     if cfg.skip {
         return Ok(None);
     }
 
-    let method_name = if let Some(custom) = cfg.custom_name {
-        Ident::new(&custom, field_name.span())
+    let field_vis = match (&cfg.vis, &struct_config.vis) {
+        (Some(vis), _) | (None, Some(vis)) => vis,
+        (None, None) => inherited_vis(input, field, site.variant.is_some()),
+    };
+
+    let method_name = match cfg.custom_name {
+        Some(custom) => method_ident(&custom, span)?,
+        None => method_ident(&format!("{}{}", struct_config.prefix, stem), span)?,
+    };
+
+    let mut method_names = vec![method_name.clone()];
+
+    let field_type = if cfg.strip_option {
+        match option_inner(&field.ty) {
+            Some(inner) => inner,
+            None => {
+                return Err(Error::new_spanned(
+                    &field.ty,
+                    "`strip_option` can only be used on `Option<T>` fields",
+                ));
+            }
+        }
+    } else {
+        &field.ty
+    };
+
+    let param_type = if cfg.into {
+        quote! { impl ::core::convert::Into<#field_type> }
     } else {
-        Ident::new(&format!("with_{}", field_name), field_name.span())
+        quote! { #field_type }
+    };
+
+    let value = match (cfg.into, cfg.strip_option) {
+        (false, false) => quote! { value },
+        (true, false) => quote! { value.into() },
+        (false, true) => quote! { ::core::option::Option::Some(value) },
+        (true, true) => quote! { ::core::option::Option::Some(value.into()) },
     };
 
-    let doc_comment = format!("Set the `{}` field.", field_name);
+    let doc_comment = format!("Set the `{}` field.", display_name);
+    let variant_doc = variant_doc(site, struct_config);
 
-    validate_field_visibility(field_vis, field_name)?;
+    let method = if let Some(variant) = site.variant {
+        let (return_ty, ok, err) = variant_results(
+            struct_config,
+            quote! { Self },
+            quote! { self },
+            quote! { self },
+        );
+
+        quote_spanned! { span =>
+            #[allow(unused, irrefutable_let_patterns)]
+            #[doc = #doc_comment]
+            #[doc = #variant_doc]
+            #[inline]
+            #field_vis fn #method_name(mut self, value: #param_type) -> #return_ty {
+                if let Self::#variant { #member: field, .. } = &mut self {
+                    *field = #value;
+                    #ok
+                } else {
+                    #err
+                }
+            }
+        }
+    } else if !cfg.into
+        && !cfg.strip_option
+        && let Some(param) = retypable_param(input, site)
+    {
+        retyping_setter(input, site, param, field_vis, &method_name, &doc_comment)
+    } else {
+        quote_spanned! { span =>
+            #[allow(unused)]
+            #[doc = #doc_comment]
+            #[inline]
+            #field_vis fn #method_name(mut self, value: #param_type) -> Self {
+                self.#member = #value;
+                self
+            }
+        }
+    };
 
-    let method = quote_spanned! { field.span() =>
+    let set_method = if cfg.set || struct_config.set {
+        let set_name = Ident::new(&format!("set_{}", stem), span);
+        method_names.push(set_name.clone());
+
+        if set_name == method_name {
+            return Err(Error::new_spanned(
+                field,
+                format!("`set` would generate a second `{}` method", set_name),
+            ));
+        }
+
+        let doc_comment = format!("Set the `{}` field in place.", display_name);
+
+        if let Some(variant) = site.variant {
+            let (return_ty, ok, err) = variant_results(
+                struct_config,
+                quote! { &mut Self },
+                quote! { self },
+                quote! { self },
+            );
+
+            quote_spanned! { span =>
+                #[allow(unused, irrefutable_let_patterns)]
+                #[doc = #doc_comment]
+                #[doc = #variant_doc]
+                #[inline]
+                #field_vis fn #set_name(&mut self, value: #param_type) -> #return_ty {
+                    if let Self::#variant { #member: field, .. } = self {
+                        *field = #value;
+                        #ok
+                    } else {
+                        #err
+                    }
+                }
+            }
+        } else {
+            quote_spanned! { span =>
+                #[allow(unused)]
+                #[doc = #doc_comment]
+                #[inline]
+                #field_vis fn #set_name(&mut self, value: #param_type) -> &mut Self {
+                    self.#member = #value;
+                    self
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    let map_method = if cfg.map || struct_config.map {
+        let map_name = Ident::new(&format!("map_{}", stem), span);
+        method_names.push(map_name.clone());
+
+        if map_name == method_name {
+            return Err(Error::new_spanned(
+                field,
+                format!("`map` would generate a second `{}` method", map_name),
+            ));
+        }
+
+        let doc_comment = format!(
+            "Replace the `{}` field with the result of calling `f` on it.",
+            display_name
+        );
+        let field_type = &field.ty;
+
+        if let Some(variant) = site.variant {
+            let members: Vec<Member> = FieldSite::all(site.fields, site.variant)
+                .iter()
+                .map(FieldSite::member)
+                .collect();
+            let bindings: Vec<Ident> = (0..members.len())
+                .map(|idx| format_ident!("__field{}", idx))
+                .collect();
+            let mapped = bindings.iter().enumerate().map(|(idx, binding)| {
+                if idx == site.index {
+                    quote! { f(#binding) }
+                } else {
+                    quote! { #binding }
+                }
+            });
+            let (return_ty, ok, err) = variant_results(
+                struct_config,
+                quote! { Self },
+                quote! { Self::#variant { #(#members: #mapped,)* } },
+                quote! { other },
+            );
+
+            quote_spanned! { span =>
+                #[allow(unused, unreachable_patterns)]
+                #[doc = #doc_comment]
+                #[doc = #variant_doc]
+                #[inline]
+                #field_vis fn #map_name(self, f: impl ::core::ops::FnOnce(#field_type) -> #field_type) -> #return_ty {
+                    match self {
+                        Self::#variant { #(#members: #bindings,)* } => #ok,
+                        other => #err,
+                    }
+                }
+            }
+        } else {
+            quote_spanned! { span =>
+                #[allow(unused)]
+                #[doc = #doc_comment]
+                #[inline]
+                #field_vis fn #map_name(mut self, f: impl ::core::ops::FnOnce(#field_type) -> #field_type) -> Self {
+                    self.#member = f(self.#member);
+                    self
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    let collection_method = match &cfg.collection {
+        Some(helper) => {
+            let (custom, default_stem, arity) = match helper {
+                CollectionHelper::Push(custom) => (custom, "item", 1),
+                CollectionHelper::Insert(custom) => (custom, "entry", 2),
+            };
+
+            let item_types = match collection_args(&field.ty) {
+                Some(types) if types.len() >= arity => types,
+                _ => {
+                    return Err(Error::new_spanned(
+                        &field.ty,
+                        if arity == 1 {
+                            "`push` needs a collection type like `Vec<T>`"
+                        } else {
+                            "`insert` needs a map type like `HashMap<K, V>`"
+                        },
+                    ));
+                }
+            };
+
+            let helper_name = match custom {
+                Some(custom) => method_ident(custom, span)?,
+                None => method_ident(
+                    &format!("{}{}_{}", struct_config.prefix, stem, default_stem),
+                    span,
+                )?,
+            };
+            method_names.push(helper_name.clone());
+
+            if helper_name == method_name {
+                return Err(Error::new_spanned(
+                    field,
+                    format!("`{}` is already the field's setter", helper_name),
+                ));
+            }
+
+            let convert = |arg: &Ident, ty: &Type| {
+                if cfg.into {
+                    (
+                        quote! { #arg: impl ::core::convert::Into<#ty> },
+                        quote! { #arg.into() },
+                    )
+                } else {
+                    (quote! { #arg: #ty }, quote! { #arg })
+                }
+            };
+
+            let (params, item, doc_comment) = if arity == 1 {
+                let (param, value) = convert(&format_ident!("item"), item_types[0]);
+                (
+                    param,
+                    value,
+                    format!("Add an item to the `{}` field.", display_name),
+                )
+            } else {
+                let (key_param, key) = convert(&format_ident!("key"), item_types[0]);
+                let (value_param, value) = convert(&format_ident!("value"), item_types[1]);
+                (
+                    quote! { #key_param, #value_param },
+                    quote! { (#key, #value) },
+                    format!("Insert an entry into the `{}` field.", display_name),
+                )
+            };
+
+            mutating_method(
+                site,
+                struct_config,
+                field_vis,
+                &helper_name,
+                &doc_comment,
+                params,
+                quote! { ::core::iter::Extend::extend(field, ::core::iter::once(#item)); },
+            )
+        }
+        None => quote! {},
+    };
+
+    let without = match cfg.without {
+        Some(custom) => Some(custom),
+        None if struct_config.without && option_inner(&field.ty).is_some() => Some(None),
+        None => None,
+    };
+
+    let without_method = if let Some(custom) = without {
+        if option_inner(&field.ty).is_none() {
+            return Err(Error::new_spanned(
+                &field.ty,
+                "`without` can only be used on `Option<T>` fields",
+            ));
+        }
+
+        // `with_` becomes `without_`, other prefixes get `no_`: `set_no_x`.
+        let without_name = match custom {
+            Some(custom) => method_ident(&custom, span)?,
+            None => match struct_config.prefix.strip_prefix("with") {
+                Some(rest) => method_ident(&format!("without{}{}", rest, stem), span)?,
+                None => method_ident(&format!("{}no_{}", struct_config.prefix, stem), span)?,
+            },
+        };
+        method_names.push(without_name.clone());
+        let doc_comment = format!("Reset the `{}` field to `None`.", display_name);
+
+        mutating_method(
+            site,
+            struct_config,
+            field_vis,
+            &without_name,
+            &doc_comment,
+            quote! {},
+            quote! { *field = ::core::option::Option::None; },
+        )
+    } else {
+        quote! {}
+    };
+
+    Ok(Some((
+        quote! { #method #set_method #map_method #collection_method #without_method },
+        field_vis.clone(),
+        method_names,
+    )))
+}
+
+/// A consuming method that updates the field through `field: &mut T`. On
+/// enums it only applies when `self` is the field's variant.
+fn mutating_method(
+    site: &FieldSite,
+    struct_config: &StructConfig,
+    field_vis: &Visibility,
+    method_name: &Ident,
+    doc_comment: &str,
+    params: TokenStream,
+    body: TokenStream,
+) -> TokenStream {
+    let member = site.member();
+
+    if let Some(variant) = site.variant {
+        let (return_ty, ok, err) = variant_results(
+            struct_config,
+            quote! { Self },
+            quote! { self },
+            quote! { self },
+        );
+        let variant_doc = variant_doc(site, struct_config);
+
+        quote_spanned! { site.field.span() =>
+            #[allow(unused, irrefutable_let_patterns)]
+            #[doc = #doc_comment]
+            #[doc = #variant_doc]
+            #[inline]
+            #field_vis fn #method_name(mut self, #params) -> #return_ty {
+                if let Self::#variant { #member: field, .. } = &mut self {
+                    #body
+                    #ok
+                } else {
+                    #err
+                }
+            }
+        }
+    } else {
+        quote_spanned! { site.field.span() =>
+            #[allow(unused)]
+            #[doc = #doc_comment]
+            #[inline]
+            #field_vis fn #method_name(mut self, #params) -> Self {
+                let field = &mut self.#member;
+                #body
+                self
+            }
+        }
+    }
+}
+
+/// A generated method name, which is rejected rather than panicking when the
+/// prefix and stem don't form an identifier, like `0` for a tuple field with
+/// an empty prefix.
+fn method_ident(name: &str, span: Span) -> Result<Ident> {
+    let mut ident = syn::parse_str::<Ident>(name).map_err(|_| {
+        Error::new(
+            span,
+            format!(
+                "`{}` is not a valid method name, use a different `prefix` or \
+                 `rename = \"...\"`",
+                name
+            ),
+        )
+    })?;
+    ident.set_span(span);
+    Ok(ident)
+}
+
+/// The type arguments of a collection type's last path segment, like `[T]`
+/// for `Vec<T>` or `[K, V]` for `HashMap<K, V>`.
+fn collection_args(ty: &Type) -> Option<Vec<&Type>> {
+    let Type::Path(type_path) = ty else {
+        return None;
+    };
+    let PathArguments::AngleBracketed(args) = &type_path.path.segments.last()?.arguments else {
+        return None;
+    };
+
+    Some(
+        args.args
+            .iter()
+            .filter_map(|arg| match arg {
+                GenericArgument::Type(ty) => Some(ty),
+                _ => None,
+            })
+            .collect(),
+    )
+}
+
+/// The return type of a variant field method and its results when `self` is
+/// and is not that variant; the latter hands `self` back unchanged.
+fn variant_results(
+    struct_config: &StructConfig,
+    ty: TokenStream,
+    ok: TokenStream,
+    err: TokenStream,
+) -> (TokenStream, TokenStream, TokenStream) {
+    if struct_config.result {
+        (
+            quote! { ::core::result::Result<#ty, #ty> },
+            quote! { ::core::result::Result::Ok(#ok) },
+            quote! { ::core::result::Result::Err(#err) },
+        )
+    } else {
+        (ty, ok, err)
+    }
+}
+
+fn variant_doc(site: &FieldSite, struct_config: &StructConfig) -> String {
+    match site.variant {
+        Some(variant) if struct_config.result => format!(
+            "Returns `Err(self)` unchanged if `self` is not a `{}`.",
+            variant
+        ),
+        Some(variant) => format!("Does nothing if `self` is not a `{}`.", variant),
+        None => String::new(),
+    }
+}
+
+/// The type parameter a field's type consists of, if nothing else in the
+/// struct mentions it, so that its setter can change it.
+fn retypable_param<'a>(input: &'a DeriveInput, site: &FieldSite) -> Option<&'a TypeParam> {
+    let Type::Path(type_path) = &site.field.ty else {
+        return None;
+    };
+    let ident = type_path.path.get_ident()?;
+
+    if type_path.qself.is_some() {
+        return None;
+    }
+
+    let param = input
+        .generics
+        .type_params()
+        .find(|param| &param.ident == ident)?;
+
+    let used_elsewhere = site
+        .fields
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| *index != site.index)
+        .any(|(_, other)| mentions(other.ty.to_token_stream(), ident))
+        || mentions(param.bounds.to_token_stream(), ident)
+        || input.generics.params.iter().any(|other| match other {
+            GenericParam::Type(other) => {
+                other.ident != *ident && mentions(other.to_token_stream(), ident)
+            }
+            GenericParam::Const(other) => mentions(other.ty.to_token_stream(), ident),
+            GenericParam::Lifetime(_) => false,
+        })
+        || input
+            .generics
+            .where_clause
+            .as_ref()
+            .is_some_and(|where_clause| mentions(where_clause.to_token_stream(), ident));
+
+    (!used_elsewhere).then_some(param)
+}
+
+/// Whether `ident` appears anywhere in `tokens`.
+fn mentions(tokens: TokenStream, ident: &Ident) -> bool {
+    tokens.into_iter().any(|token| match token {
+        TokenTree::Ident(other) => other == *ident,
+        TokenTree::Group(group) => mentions(group.stream(), ident),
+        _ => false,
+    })
+}
+
+/// `with_x<T2>(self, value: T2) -> Foo<T2>` for a field whose type is the
+/// type parameter `T`, moving every other field into the new instance.
+fn retyping_setter(
+    input: &DeriveInput,
+    site: &FieldSite,
+    param: &TypeParam,
+    field_vis: &Visibility,
+    method_name: &Ident,
+    doc_comment: &str,
+) -> TokenStream {
+    let name = &input.ident;
+    let bounds = &param.bounds;
+
+    let mut new_param = format_ident!("{}2", param.ident);
+    while input.generics.params.iter().any(|other| match other {
+        GenericParam::Type(other) => other.ident == new_param,
+        GenericParam::Const(other) => other.ident == new_param,
+        GenericParam::Lifetime(_) => false,
+    }) {
+        new_param = format_ident!("{}2", new_param);
+    }
+
+    let type_args = input.generics.params.iter().map(|other| match other {
+        GenericParam::Type(other) if other.ident == param.ident => quote! { #new_param },
+        GenericParam::Type(other) => other.ident.to_token_stream(),
+        GenericParam::Lifetime(other) => other.lifetime.to_token_stream(),
+        GenericParam::Const(other) => other.ident.to_token_stream(),
+    });
+
+    let moved_fields = FieldSite::all(site.fields, None).into_iter().map(|other| {
+        let other_member = other.member();
+        let value = if other.index == site.index {
+            quote! { value }
+        } else {
+            quote! { self.#other_member }
+        };
+
+        match other_member {
+            Member::Named(_) => quote! { #other_member: #value },
+            Member::Unnamed(_) => value,
+        }
+    });
+    let construct = match site.fields {
+        Fields::Unnamed(_) => quote! { #name(#(#moved_fields,)*) },
+        _ => quote! { #name { #(#moved_fields,)* } },
+    };
+
+    let colon = if bounds.is_empty() {
+        quote! {}
+    } else {
+        quote! { : }
+    };
+
+    quote_spanned! { site.field.span() =>
         #[allow(unused)]
         #[doc = #doc_comment]
         #[inline]
-        #field_vis fn #method_name(mut self, value: #field_type) -> Self {
-            self.#field_name = value;
-            self
+        #field_vis fn #method_name<#new_param #colon #bounds>(self, value: #new_param) -> #name<#(#type_args),*> {
+            #construct
         }
-    };
-
-    Ok(Some(method))
+    }
 }
 
 pub(crate) fn parse_builder_attrs(attrs: &[syn::Attribute]) -> Result<FieldConfig> {
     let mut config = FieldConfig {
         skip: false,
         custom_name: None,
+        into: false,
+        strip_option: false,
+        vis: None,
+        set: false,
+        map: false,
+        collection: None,
+        without: None,
     };
 
     for attr in attrs {
@@ -153,10 +813,85 @@ pub(crate) fn parse_builder_attrs(attrs: &[syn::Attribute]) -> Result<FieldConfi
 
                 config.custom_name = Some(name);
                 Ok(())
+            } else if meta.path.is_ident("into") {
+                if config.into {
+                    return Err(meta.error("duplicate `into` attribute"));
+                }
+
+                config.into = true;
+                Ok(())
+            } else if meta.path.is_ident("strip_option") {
+                if config.strip_option {
+                    return Err(meta.error("duplicate `strip_option` attribute"));
+                }
+
+                config.strip_option = true;
+                Ok(())
+            } else if meta.path.is_ident("vis") {
+                if config.vis.is_some() {
+                    return Err(meta.error("duplicate `vis` attribute"));
+                }
+
+                let s: syn::LitStr = meta.value()?.parse()?;
+                config.vis = Some(s.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("set") || meta.path.is_ident("map") {
+                let flag = if meta.path.is_ident("set") {
+                    &mut config.set
+                } else {
+                    &mut config.map
+                };
+
+                if *flag {
+                    return Err(meta.error(format!(
+                        "duplicate `{}` attribute",
+                        meta.path.get_ident().unwrap()
+                    )));
+                }
+
+                *flag = true;
+                Ok(())
+            } else if meta.path.is_ident("push") || meta.path.is_ident("insert") {
+                if config.collection.is_some() {
+                    return Err(meta.error("only one of `push` and `insert` can be used"));
+                }
+
+                let name = if meta.input.peek(syn::Token![=]) {
+                    let s: syn::LitStr = meta.value()?.parse()?;
+                    syn::parse_str::<Ident>(&s.value())
+                        .map_err(|_| Error::new_spanned(&s, "expected a method name"))?;
+                    Some(s.value())
+                } else {
+                    None
+                };
+
+                config.collection = Some(if meta.path.is_ident("push") {
+                    CollectionHelper::Push(name)
+                } else {
+                    CollectionHelper::Insert(name)
+                });
+                Ok(())
+            } else if meta.path.is_ident("without") {
+                if config.without.is_some() {
+                    return Err(meta.error("duplicate `without` attribute"));
+                }
+
+                let name = if meta.input.peek(syn::Token![=]) {
+                    let s: syn::LitStr = meta.value()?.parse()?;
+                    syn::parse_str::<Ident>(&s.value())
+                        .map_err(|_| Error::new_spanned(&s, "expected a method name"))?;
+                    Some(s.value())
+                } else {
+                    None
+                };
+
+                config.without = Some(name);
+                Ok(())
             } else {
                 Err(meta.error(format!(
                     "unknown builder attribute `{}`. \
-                            Valid attributes are: `skip`, `rename = \"...\"`",
+                            Valid attributes are: `skip`, `rename = \"...\"`, `into`, `strip_option`, \
+                            `vis = \"...\"`, `set`, `map`, `push`, `insert`, `without`",
                     meta.path
                         .get_ident()
                         .map(|i| i.to_string())
@@ -169,8 +904,133 @@ pub(crate) fn parse_builder_attrs(attrs: &[syn::Attribute]) -> Result<FieldConfi
     Ok(config)
 }
 
-pub(crate) fn validate_field_visibility(vis: &Visibility, _field_name: &Ident) -> Result<()> {
-    if let Visibility::Inherited = vis {}
+pub(crate) fn parse_struct_attrs(attrs: &[syn::Attribute]) -> Result<StructConfig> {
+    let mut prefix = None;
+    let mut vis = None;
+    let mut set = false;
+    let mut map = false;
+    let mut without = false;
+    let mut result = false;
+
+    for attr in attrs {
+        if !attr.path().is_ident("builder") {
+            continue;
+        }
+
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("prefix") {
+                if prefix.is_some() {
+                    return Err(meta.error("duplicate `prefix` attribute"));
+                }
+
+                let value = meta.value()?;
+                let s: syn::LitStr = value.parse()?;
+                let name = s.value();
+
+                if let Some(first) = name.chars().next()
+                    && !first.is_alphabetic()
+                    && first != '_'
+                {
+                    return Err(Error::new_spanned(
+                        s,
+                        "prefix must start with a letter or underscore",
+                    ));
+                }
+
+                if !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                    return Err(Error::new_spanned(
+                        s,
+                        "prefix can only contain letters, digits and underscores",
+                    ));
+                }
+
+                prefix = Some(name);
+                Ok(())
+            } else if meta.path.is_ident("vis") {
+                if vis.is_some() {
+                    return Err(meta.error("duplicate `vis` attribute"));
+                }
+
+                let s: syn::LitStr = meta.value()?.parse()?;
+                vis = Some(s.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("set") || meta.path.is_ident("map") {
+                let flag = if meta.path.is_ident("set") {
+                    &mut set
+                } else {
+                    &mut map
+                };
+
+                if *flag {
+                    return Err(meta.error(format!(
+                        "duplicate `{}` attribute",
+                        meta.path.get_ident().unwrap()
+                    )));
+                }
+
+                *flag = true;
+                Ok(())
+            } else if meta.path.is_ident("result") {
+                if result {
+                    return Err(meta.error("duplicate `result` attribute"));
+                }
+
+                result = true;
+                Ok(())
+            } else if meta.path.is_ident("without") {
+                if without {
+                    return Err(meta.error("duplicate `without` attribute"));
+                }
+
+                without = true;
+                Ok(())
+            } else {
+                Err(meta.error(format!(
+                    "unknown builder attribute `{}`. \
+                            Valid attributes are: `prefix = \"...\"`, `vis = \"...\"`, `set`, `map`, \
+                            `without`, `result`",
+                    meta.path
+                        .get_ident()
+                        .map(|i| i.to_string())
+                        .unwrap_or_default()
+                )))
+            }
+        })?;
+    }
+
+    Ok(StructConfig {
+        prefix: prefix.unwrap_or_else(|| String::from("with_")),
+        vis,
+        set,
+        map,
+        without,
+        result,
+    })
+}
+
+/// Rejects a `pub` struct whose setters all inherit private field visibility,
+/// which would leave it without any setter usable outside its module.
+pub(crate) fn validate_field_visibility(
+    input: &DeriveInput,
+    setter_visibilities: &[Visibility],
+) -> Result<()> {
+    let all_private = !setter_visibilities.is_empty()
+        && setter_visibilities
+            .iter()
+            .all(|vis| matches!(vis, Visibility::Inherited));
+
+    if let Visibility::Public(_) = input.vis
+        && all_private
+    {
+        return Err(Error::new_spanned(
+            &input.ident,
+            format!(
+                "`{}` is `pub` but all of its setters are private; make the fields `pub` \
+                 or add `#[builder(vis = \"pub\")]` to the struct or its fields",
+                input.ident
+            ),
+        ));
+    }
 
     Ok(())
 }
@@ -1,9 +1,10 @@
 use {
+    crate::util::option_inner,
     proc_macro2::TokenStream,
     quote::{format_ident, quote},
     syn::{
-        Data, DeriveInput, Fields, GenericParam, Generics, Ident, Meta, Result, Type, parse_quote,
-        spanned::Spanned,
+        Data, DeriveInput, Fields, GenericArgument, GenericParam, Generics, Ident, Meta,
+        PathArguments, Result, Type, parse_quote,
     },
 };
 
@@ -28,7 +29,7 @@ pub(crate) fn expand_doc_display(input: DeriveInput) -> Result<TokenStream> {
 
         quote! {
             #(
-                const _: fn() || {
+                let _ = || {
                     fn assert_display<T: std::fmt::Display>() {}
                     assert_display::<#type_params>();
                 };
@@ -39,8 +40,9 @@ pub(crate) fn expand_doc_display(input: DeriveInput) -> Result<TokenStream> {
     };
 
     let doc_str = extract_doc_comments(&input.attrs);
+    let transparent = parse_transparent(&input.attrs)?;
 
-    if matches!(&input.data, Data::Struct(_)) && doc_str.is_empty() {
+    if matches!(&input.data, Data::Struct(_)) && doc_str.is_empty() && !transparent {
         return Err(syn::Error::new_spanned(
             &input.ident,
             "DocDisplay requires doc comments on the main type for structs",
@@ -48,7 +50,16 @@ pub(crate) fn expand_doc_display(input: DeriveInput) -> Result<TokenStream> {
     }
 
     let display_impl = match &input.data {
+        Data::Struct(data_struct) if transparent => {
+            generate_transparent_display(name, &data_struct.fields)?
+        }
         Data::Struct(data_struct) => generate_struct_display(&doc_str, &data_struct.fields),
+        Data::Enum(data_enum) if transparent => {
+            return Err(syn::Error::new_spanned(
+                data_enum.enum_token,
+                "`#[display(transparent)]` is only supported on structs",
+            ));
+        }
         Data::Enum(data_enum) => generate_enum_display(name, &doc_str, data_enum),
         Data::Union(data_union) => {
             return Err(syn::Error::new_spanned(
@@ -84,71 +95,76 @@ pub(crate) fn expand_doc_display(input: DeriveInput) -> Result<TokenStream> {
 }
 
 fn generate_struct_display(doc_str: &str, fields: &Fields) -> proc_macro2::TokenStream {
-    let field_refs = find_field_refs(doc_str);
-    let field_names: Vec<String> = match fields {
-        Fields::Named(fields_named) => {
-            for field in fields_named.named.iter() {
-                if let Type::Path(type_path) = &field.ty
-                    && let Some(last_seg) = type_path.path.segments.last()
-                    && (last_seg.ident == "Option" || last_seg.ident == "Result")
-                {
-                    return syn::Error::new(
-                        field.ty.span(),
-                        format!(
-                            "DocDisplay doesn't support `{}`s yet",
-                            field.ty.span().source_text().unwrap()
-                        ),
-                    )
-                    .to_compile_error();
-                }
-            }
-
-            fields_named
-                .named
-                .iter()
-                .filter_map(|f| f.ident.as_ref().map(|i| i.to_string()))
-                .collect()
-        }
-        Fields::Unnamed(_) => {
-            return syn::Error::new(
-                proc_macro2::Span::call_site(),
-                "DocDisplay doesn't support tuple structs with field interpolation",
-            )
-            .to_compile_error();
+    let template = match parse_template(doc_str) {
+        Ok(template) => template,
+        Err(message) => {
+            return syn::Error::new(proc_macro2::Span::call_site(), message).to_compile_error();
         }
-        Fields::Unit => Vec::new(),
+    };
+    let members = field_members(fields);
+    let configs = match fields
+        .iter()
+        .map(parse_field_attrs)
+        .collect::<Result<Vec<_>>>()
+    {
+        Ok(configs) => configs,
+        Err(err) => return err.to_compile_error(),
     };
 
-    for field_ref in &field_refs {
-        if !field_names.contains(field_ref) {
+    let mut fmt_args = Vec::new();
+    let mut pieces = Vec::new();
+
+    for placeholder in &template.placeholders {
+        let Some(idx) = members
+            .iter()
+            .position(|(_, name)| *name == placeholder.root)
+        else {
             return syn::Error::new(
                 proc_macro2::Span::call_site(),
                 format!(
                     "Field `{}` referenced in doc comment doesn't exist",
-                    field_ref
+                    placeholder.root
                 ),
             )
             .to_compile_error();
+        };
+
+        let member = &members[idx].0;
+        let field = fields.iter().nth(idx).unwrap();
+        match placeholder.arg(quote! { self.#member }, field, &configs[idx]) {
+            Ok((arg, piece)) => {
+                fmt_args.push(arg);
+                pieces.push(piece);
+            }
+            Err(message) => {
+                return syn::Error::new(proc_macro2::Span::call_site(), message).to_compile_error();
+            }
         }
     }
 
-    if field_refs.is_empty() {
-        quote! {
-            write!(f, #doc_str)
-        }
-    } else {
-        let fmt_str = build_fmt_str(doc_str);
-        let fmt_args = field_refs.iter().map(|name| {
-            let ident = Ident::new(name, proc_macro2::Span::call_site());
-            quote! { self.#ident }
-        });
+    let fmt_str = template.fmt_str(&pieces);
 
-        quote! {
-            write!(f, #fmt_str, #(#fmt_args),*)
-        }
+    quote! {
+        write!(f, #fmt_str, #(#fmt_args),*)
     }
 }
 
+/// Forwards to the `Display` impl of the struct's only field.
+fn generate_transparent_display(name: &Ident, fields: &Fields) -> Result<TokenStream> {
+    let members = field_members(fields);
+
+    let [(member, _)] = members.as_slice() else {
+        return Err(syn::Error::new_spanned(
+            name,
+            "`#[display(transparent)]` requires a struct with exactly one field",
+        ));
+    };
+
+    Ok(quote! {
+        ::core::fmt::Display::fmt(&self.#member, f)
+    })
+}
+
 fn generate_enum_display(
     enum_name: &Ident,
     doc_str: &str,
@@ -160,49 +176,40 @@ fn generate_enum_display(
         .any(|v| !extract_doc_comments(&v.attrs).is_empty());
 
     if !has_variant_docs {
-        return quote! {
-            write!(f, #doc_str)
-        };
-    }
-
-    let match_arms = data_enum.variants.iter().map(|variant| {
-        let variant_name = &variant.ident;
-        let variant_doc = extract_doc_comments(&variant.attrs);
-
-        let display_str = if variant_doc.is_empty() {
-            format!("{}::{}", enum_name, variant_name)
-        } else {
-            variant_doc
-        };
-
-        match &variant.fields {
-            Fields::Unit => {
+        return match parse_template(doc_str) {
+            Ok(template) if template.placeholders.is_empty() => {
+                let fmt_str = template.fmt_str(&[]);
                 quote! {
-                    Self::#variant_name => write!(f, #display_str)
+                    write!(f, #fmt_str)
                 }
             }
-            Fields::Unnamed(fields) => {
-                let field_bindings = (0..fields.unnamed.len())
-                    .map(|i| Ident::new(&format!("_{}", i), proc_macro2::Span::call_site()))
-                    .collect::<Vec<_>>();
-
-                quote! {
-                    Self::#variant_name(#(#field_bindings),*) => write!(f, #display_str)
-                }
+            Ok(_) => syn::Error::new(
+                proc_macro2::Span::call_site(),
+                "Placeholders in an enum's doc comment need doc comments on its variants instead",
+            )
+            .to_compile_error(),
+            Err(message) => {
+                syn::Error::new(proc_macro2::Span::call_site(), message).to_compile_error()
             }
-            Fields::Named(fields) => {
-                let field_names = fields
-                    .named
-                    .iter()
-                    .map(|f| f.ident.as_ref().unwrap())
-                    .collect::<Vec<_>>();
+        };
+    }
 
-                quote! {
-                    Self::#variant_name { #(#field_names),* } => write!(f, #display_str)
-                }
-            }
+    let mut match_arms = Vec::new();
+    let mut error: Option<syn::Error> = None;
+
+    for variant in &data_enum.variants {
+        match variant_display_arm(enum_name, variant) {
+            Ok(arm) => match_arms.push(arm),
+            Err(err) => match &mut error {
+                Some(error) => error.combine(err),
+                None => error = Some(err),
+            },
         }
-    });
+    }
+
+    if let Some(error) = error {
+        return error.to_compile_error();
+    }
 
     quote! {
         match self {
@@ -211,6 +218,86 @@ fn generate_enum_display(
     }
 }
 
+/// `Self::Variant { .. } => write!(f, ...)`, binding only the fields the
+/// variant's doc comment refers to.
+fn variant_display_arm(enum_name: &Ident, variant: &syn::Variant) -> Result<TokenStream> {
+    let variant_name = &variant.ident;
+    let variant_doc = extract_doc_comments(&variant.attrs);
+
+    let display_str = if variant_doc.is_empty() {
+        format!("{}::{}", enum_name, variant_name)
+    } else {
+        variant_doc
+    };
+
+    let template = parse_template(&display_str)
+        .map_err(|message| syn::Error::new_spanned(variant_name, message))?;
+
+    let members = field_members(&variant.fields);
+    let configs = variant
+        .fields
+        .iter()
+        .map(parse_field_attrs)
+        .collect::<Result<Vec<_>>>()?;
+
+    // Fields are bound to `__field0`, `__field1`, ... so that they can't
+    // shadow the formatter.
+    let mut used = vec![false; members.len()];
+    let mut fmt_args = Vec::new();
+    let mut pieces = Vec::new();
+
+    for placeholder in &template.placeholders {
+        let Some(idx) = members
+            .iter()
+            .position(|(_, name)| *name == placeholder.root)
+        else {
+            return Err(syn::Error::new_spanned(
+                variant_name,
+                format!(
+                    "Field `{}` referenced in doc comment of variant `{}` doesn't exist",
+                    placeholder.root, variant_name
+                ),
+            ));
+        };
+
+        used[idx] = true;
+        let binding = format_ident!("__field{}", idx);
+        let field = variant.fields.iter().nth(idx).unwrap();
+        let (arg, piece) = placeholder
+            .arg(quote! { #binding }, field, &configs[idx])
+            .map_err(|message| syn::Error::new_spanned(variant_name, message))?;
+        fmt_args.push(arg);
+        pieces.push(piece);
+    }
+
+    let bindings = members
+        .iter()
+        .enumerate()
+        .filter(|(idx, _)| used[*idx])
+        .map(|(idx, (member, _))| {
+            let binding = format_ident!("__field{}", idx);
+            quote! { #member: #binding }
+        });
+
+    let fmt_str = template.fmt_str(&pieces);
+
+    Ok(quote! {
+        Self::#variant_name { #(#bindings,)* .. } => write!(f, #fmt_str, #(#fmt_args),*)
+    })
+}
+
+/// Each field's member, `name` or `0`, and the name placeholders use for it.
+fn field_members(fields: &Fields) -> Vec<(syn::Member, String)> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(idx, field)| match &field.ident {
+            Some(ident) => (syn::Member::Named(ident.clone()), ident.to_string()),
+            None => (syn::Member::Unnamed(syn::Index::from(idx)), idx.to_string()),
+        })
+        .collect()
+}
+
 fn extract_doc_comments(attrs: &[syn::Attribute]) -> String {
     let mut doc_string = String::new();
 
@@ -234,14 +321,433 @@ fn extract_doc_comments(attrs: &[syn::Attribute]) -> String {
     doc_string
 }
 
-fn find_field_refs(doc_str: &str) -> Vec<String> {
-    let re = regex::Regex::new(r"\{(\w+)\}").unwrap();
-    re.captures_iter(doc_str)
-        .filter_map(|cap| cap.get(1).map(|m| m.as_str().to_string()))
-        .collect()
+/// A doc comment split into its placeholders and the literal text around
+/// them, with `{{` and `}}` kept escaped.
+struct Template {
+    /// One more than there are placeholders.
+    literals: Vec<String>,
+    placeholders: Vec<Placeholder>,
+}
+
+impl Template {
+    /// The `format!` string, putting `pieces[i]` (`{}` or `{:spec}`) in place
+    /// of the `i`th placeholder.
+    fn fmt_str(&self, pieces: &[String]) -> String {
+        let mut fmt_str = self.literals[0].clone();
+
+        for (piece, literal) in pieces.iter().zip(&self.literals[1..]) {
+            fmt_str.push_str(piece);
+            fmt_str.push_str(literal);
+        }
+
+        fmt_str
+    }
+}
+
+/// A `{root.path:spec}` placeholder, like `{value:.2}` or `{items.len()}`.
+struct Placeholder {
+    /// The field the placeholder starts from.
+    root: String,
+    /// Fields and zero-argument method calls applied to the root, in order.
+    path: Vec<PathSegment>,
+    /// Whether the field was written as `{field?}`.
+    optional: bool,
+    /// The text from `{field?:"..."}`, shown for `None` and `Err`.
+    fallback: Option<String>,
+    /// The format spec after the last `:`, like `.2` or `>8`.
+    spec: Option<String>,
+}
+
+enum PathSegment {
+    Field(String),
+    Method(String),
+}
+
+impl Placeholder {
+    /// The expression formatted for this placeholder, starting from `root`.
+    fn expr(&self, root: TokenStream) -> TokenStream {
+        let path = self.path.iter().map(|segment| match segment {
+            PathSegment::Field(name) => match name.parse::<usize>() {
+                Ok(index) => {
+                    let index = syn::Index::from(index);
+                    quote! { .#index }
+                }
+                Err(_) => {
+                    let ident = Ident::new(name, proc_macro2::Span::call_site());
+                    quote! { .#ident }
+                }
+            },
+            PathSegment::Method(name) => {
+                let ident = Ident::new(name, proc_macro2::Span::call_site());
+                quote! { .#ident() }
+            }
+        });
+
+        quote! { #root #(#path)* }
+    }
+
+    /// `{}` or `{:spec}`.
+    fn fmt_piece(spec: Option<&str>) -> String {
+        match spec {
+            Some(spec) => format!("{{:{}}}", spec),
+            None => String::from("{}"),
+        }
+    }
+
+    /// The format argument for this placeholder and its piece of the format
+    /// string. A bare `Option` or `Result` field writes its inner value with
+    /// the placeholder's spec, or the fallback text when there is none, so
+    /// its piece is a plain `{}`.
+    fn arg(
+        &self,
+        root: TokenStream,
+        field: &syn::Field,
+        config: &FieldConfig,
+    ) -> std::result::Result<(TokenStream, String), String> {
+        let expr = self.expr(root);
+        let piece = Self::fmt_piece(self.spec.as_deref());
+
+        if !self.path.is_empty() {
+            return Ok((expr, piece));
+        }
+
+        let Some(wrapper) = wrapper_kind(&field.ty) else {
+            if self.optional {
+                return Err(format!(
+                    "`{{{}?}}` can only be used on `Option` and `Result` fields",
+                    self.root
+                ));
+            }
+
+            return Ok((expr, piece));
+        };
+
+        let fallback = self
+            .fallback
+            .as_ref()
+            .or(config.none.as_ref())
+            .map(String::as_str)
+            .unwrap_or_default();
+        let (some, none) = match wrapper {
+            Wrapper::Option => (
+                quote! { ::core::option::Option::Some(value) },
+                quote! { ::core::option::Option::None },
+            ),
+            Wrapper::Result => (
+                quote! { ::core::result::Result::Ok(value) },
+                quote! { ::core::result::Result::Err(_) },
+            ),
+        };
+
+        // The fallback is text, so it keeps only the spec's fill, alignment
+        // and width.
+        let fallback_piece = Self::fmt_piece(self.spec.as_deref().map(text_spec));
+
+        Ok((
+            quote! {
+                {
+                    struct __DisplayWith<F>(F)
+                    where
+                        F: ::core::ops::Fn(&mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result;
+
+                    impl<F> ::core::fmt::Display for __DisplayWith<F>
+                    where
+                        F: ::core::ops::Fn(&mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result,
+                    {
+                        fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                            (self.0)(f)
+                        }
+                    }
+
+                    __DisplayWith(|f: &mut ::core::fmt::Formatter<'_>| match &#expr {
+                        #some => ::core::write!(f, #piece, value),
+                        #none => ::core::write!(f, #fallback_piece, #fallback),
+                    })
+                }
+            },
+            String::from("{}"),
+        ))
+    }
+}
+
+/// Options from a field's `#[display(...)]` attributes.
+struct FieldConfig {
+    /// Text shown for `None`/`Err` when the placeholder gives none.
+    none: Option<String>,
+}
+
+enum Wrapper {
+    Option,
+    Result,
+}
+
+/// Whether `ty` is spelled `Option<T>`, or `Result<T, ..>` like
+/// `io::Result<T>`. A bare `fmt::Result` has no value to show.
+fn wrapper_kind(ty: &Type) -> Option<Wrapper> {
+    if option_inner(ty).is_some() {
+        return Some(Wrapper::Option);
+    }
+
+    if let Type::Path(type_path) = ty
+        && type_path.qself.is_none()
+        && let Some(last_seg) = type_path.path.segments.last()
+        && last_seg.ident == "Result"
+        && let PathArguments::AngleBracketed(args) = &last_seg.arguments
+        && let Some(GenericArgument::Type(_)) = args.args.first()
+    {
+        return Some(Wrapper::Result);
+    }
+
+    None
+}
+
+/// `spec` without its precision and formatting trait, keeping the fill,
+/// alignment and width: `>8.2` and `>8x` both become `>8`.
+fn text_spec(spec: &str) -> &str {
+    let spec = spec.strip_suffix('?').unwrap_or(spec);
+    let spec = spec
+        .strip_suffix(['x', 'X', 'o', 'b', 'e', 'E', 'p'])
+        .unwrap_or(spec);
+
+    match spec.rsplit_once('.') {
+        Some((rest, precision))
+            if !precision.is_empty() && precision.chars().all(|c| c.is_ascii_digit()) =>
+        {
+            rest
+        }
+        _ => spec,
+    }
+}
+
+fn parse_transparent(attrs: &[syn::Attribute]) -> Result<bool> {
+    let mut transparent = false;
+
+    for attr in attrs {
+        if !attr.path().is_ident("display") {
+            continue;
+        }
+
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("transparent") {
+                if transparent {
+                    return Err(meta.error("duplicate `transparent` attribute"));
+                }
+
+                transparent = true;
+                Ok(())
+            } else {
+                Err(meta.error(format!(
+                    "unknown display attribute `{}`. Valid attributes are: `transparent`",
+                    meta.path
+                        .get_ident()
+                        .map_or(String::new(), |i| i.to_string())
+                )))
+            }
+        })?;
+    }
+
+    Ok(transparent)
 }
 
-fn build_fmt_str(doc_str: &str) -> String {
-    let re = regex::Regex::new(r"\{(\w+)\}").unwrap();
-    re.replace_all(doc_str, "{}").to_string()
+fn parse_field_attrs(field: &syn::Field) -> Result<FieldConfig> {
+    let mut config = FieldConfig { none: None };
+
+    for attr in &field.attrs {
+        if !attr.path().is_ident("display") {
+            continue;
+        }
+
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("none") {
+                if config.none.is_some() {
+                    return Err(meta.error("duplicate `none` attribute"));
+                }
+
+                let s: syn::LitStr = meta.value()?.parse()?;
+
+                if wrapper_kind(&field.ty).is_none() {
+                    return Err(syn::Error::new_spanned(
+                        s,
+                        "`none` can only be used on `Option` and `Result` fields",
+                    ));
+                }
+
+                config.none = Some(s.value());
+                Ok(())
+            } else {
+                Err(meta.error(format!(
+                    "unknown display attribute `{}`. Valid attributes are: `none = \"...\"`",
+                    meta.path
+                        .get_ident()
+                        .map_or(String::new(), |i| i.to_string())
+                )))
+            }
+        })?;
+    }
+
+    Ok(config)
+}
+
+/// Splits a doc comment into literal text and placeholders. `{{` and `}}`
+/// stay escaped in the format string.
+fn parse_template(doc_str: &str) -> std::result::Result<Template, String> {
+    let mut fmt_str = String::new();
+    let mut literals = Vec::new();
+    let mut placeholders = Vec::new();
+    let mut chars = doc_str.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                fmt_str.push_str("{{");
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                fmt_str.push_str("}}");
+            }
+            '}' => {
+                return Err(String::from(
+                    "unmatched `}` in doc comment, use `}}` for a literal brace",
+                ));
+            }
+            '{' => {
+                // A quoted fallback may itself contain braces.
+                let mut content = String::new();
+                let mut in_fallback = false;
+                loop {
+                    match chars.next() {
+                        Some('}') if !in_fallback => break,
+                        Some('\\') if in_fallback => {
+                            content.push('\\');
+                            content.extend(chars.next());
+                        }
+                        Some('"') if in_fallback || content.ends_with("?:") => {
+                            in_fallback = !in_fallback;
+                            content.push('"');
+                        }
+                        Some(ch) => content.push(ch),
+                        None => {
+                            return Err(String::from(
+                                "unclosed `{` in doc comment, use `{{` for a literal brace",
+                            ));
+                        }
+                    }
+                }
+
+                let placeholder = parse_placeholder(&content)?;
+
+                if let Some(spec) = &placeholder.spec
+                    && (spec.contains('$') || spec.contains('*'))
+                {
+                    return Err(format!(
+                        "`{{{}}}`: widths and precisions taken from arguments are not supported",
+                        content
+                    ));
+                }
+
+                placeholders.push(placeholder);
+                literals.push(std::mem::take(&mut fmt_str));
+            }
+            ch => fmt_str.push(ch),
+        }
+    }
+
+    literals.push(fmt_str);
+
+    Ok(Template {
+        literals,
+        placeholders,
+    })
+}
+
+/// Parses the inside of a placeholder: a `field`, `field.inner`, `field.0` or
+/// `field.method()` chain, then an optional `?` or `?:"fallback"`, then an
+/// optional `:spec`.
+fn parse_placeholder(content: &str) -> std::result::Result<Placeholder, String> {
+    let is_name = |name: &str| {
+        !name.is_empty()
+            && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+            && (name.chars().all(|c| c.is_ascii_digit())
+                || !name.starts_with(|c: char| c.is_ascii_digit()))
+    };
+    let invalid = || {
+        format!(
+            "invalid placeholder `{{{}}}` in doc comment, expected a field name \
+             optionally followed by `.field`, `.method()` or `?:\"fallback\"`",
+            content
+        )
+    };
+
+    let (path, mut rest) = content.split_at(content.find([':', '?']).unwrap_or(content.len()));
+    let mut optional = false;
+    let mut fallback = None;
+
+    if let Some(after) = rest.strip_prefix('?') {
+        optional = true;
+        rest = after;
+
+        if let Some(quoted) = rest.strip_prefix(":\"") {
+            let (text, after) = parse_fallback(quoted).ok_or_else(invalid)?;
+            fallback = Some(text);
+            rest = after;
+        }
+    }
+
+    let spec = match rest.strip_prefix(':') {
+        Some(spec) => Some(spec),
+        None if rest.is_empty() => None,
+        None => return Err(invalid()),
+    };
+
+    let mut segments = path.trim().split('.');
+    let root = segments.next().unwrap_or_default();
+
+    if !is_name(root) {
+        return Err(invalid());
+    }
+
+    let path: Vec<_> = segments
+        .map(|segment| match segment.strip_suffix("()") {
+            Some(method)
+                if is_name(method) && !method.starts_with(|c: char| c.is_ascii_digit()) =>
+            {
+                Ok(PathSegment::Method(method.to_owned()))
+            }
+            None if is_name(segment) => Ok(PathSegment::Field(segment.to_owned())),
+            _ => Err(invalid()),
+        })
+        .collect::<std::result::Result<_, _>>()?;
+
+    if optional && !path.is_empty() {
+        return Err(format!(
+            "`{{{}}}`: `?` must directly follow a field name",
+            content
+        ));
+    }
+
+    Ok(Placeholder {
+        root: root.to_owned(),
+        path,
+        optional,
+        fallback,
+        spec: spec.map(str::to_owned),
+    })
+}
+
+/// Reads a fallback up to its closing quote, returning the unescaped text and
+/// whatever follows the quote.
+fn parse_fallback(quoted: &str) -> Option<(String, &str)> {
+    let mut text = String::new();
+    let mut chars = quoted.char_indices();
+
+    while let Some((idx, ch)) = chars.next() {
+        match ch {
+            '\\' => text.push(chars.next()?.1),
+            '"' => return Some((text, &quoted[idx + 1..])),
+            ch => text.push(ch),
+        }
+    }
+
+    None
 }
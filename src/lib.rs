@@ -33,9 +33,40 @@ mod mathinator2000;
 #[cfg(feature = "swizzle")]
 mod swizzle;
 
+#[cfg(any(feature = "builder", feature = "builder_lite", feature = "doc_display"))]
+mod util;
+
 #[cfg(feature = "builder_lite")]
 #[proc_macro_derive(BuilderLite, attributes(builder))]
 /// Automatically implements the builder lite pattern for a struct
+///
+/// Every field gets a `with_field(self, value) -> Self` method.
+/// `#[builder(prefix = "set_")]` on the struct changes the `with_` prefix, and
+/// fields accept `skip`, `rename = "..."`, `into` and `strip_option`, which
+/// makes the setter of an `Option<T>` field take a `T`.
+///
+/// Generic structs are supported. A field whose type is a type parameter used
+/// nowhere else gets a type-changing setter, so `with_storage<S2>(self, value:
+/// S2)` returns a `Foo<S2>`.
+///
+/// Setters share their field's visibility unless `#[builder(vis = "...")]` is
+/// given on the field or the struct. A `pub` struct whose setters would all be
+/// private is rejected.
+///
+/// `#[builder(set)]` and `#[builder(map)]`, on a field or the whole struct, add
+/// `set_field(&mut self, value) -> &mut Self` for in-place updates and
+/// `map_field(self, f) -> Self` to transform the current value.
+///
+/// Tuple struct fields get `with_0`, `with_1`, ... On an enum, the fields of
+/// each variant get `with_variant_field` methods that leave other variants
+/// unchanged, or return `Err(self)` for them with `#[builder(result)]`.
+///
+/// Collection fields marked `#[builder(push)]` or `#[builder(insert)]` also get
+/// `with_field_item(self, item)` or `with_field_entry(self, key, value)`, or a
+/// method named by `push = "..."`/`insert = "..."`. `#[builder(without)]`, on
+/// an `Option` field or the whole struct, adds `without_field(self)` to reset
+/// the field to `None`; other prefixes name it `set_no_field`, or use
+/// `without = "..."`.
 pub fn derive_builder_lite(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = syn::parse_macro_input!(input as syn::DeriveInput);
 
@@ -45,10 +76,26 @@ pub fn derive_builder_lite(input: proc_macro::TokenStream) -> proc_macro::TokenS
 }
 
 #[cfg(feature = "doc_display")]
-#[proc_macro_derive(DocDisplay)]
+#[proc_macro_derive(DocDisplay, attributes(display))]
 /// Automatically generates an `std::fmt::Display` implementation
 /// for structs and enums based on the documentation comments of the
 /// given struct/enum.
+///
+/// Placeholders name a field and take `format!` specs, like `{value:.2}` or
+/// `{id:>8}`, and can follow fields and call methods, like `{inner.name}` or
+/// `{items.len()}`. `{{` and `}}` produce literal braces.
+///
+/// Enum variant docs can refer to the variant's own fields, by name or as
+/// `{0}`, `{1}`, ... for tuple variants.
+///
+/// `Option` and `Result` fields show their inner value, formatted with the
+/// placeholder's spec, or nothing for `None` and `Err`. Give fallback text with
+/// `{name?:"<unnamed>"}` in the template or `#[display(none = "n/a")]` on the
+/// field; it keeps the spec's fill, alignment and width.
+///
+/// Tuple structs refer to their fields as `{0}`, `{1}`, ..., like `/// Point({0},
+/// {1})`. `#[display(transparent)]` on a struct with a single field forwards
+/// to that field's `Display` instead of using the doc comment.
 pub fn derive_doc_display(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = syn::parse_macro_input!(input as syn::DeriveInput);
 
@@ -133,8 +180,76 @@ pub fn autoconstruct(input: proc_macro::TokenStream) -> proc_macro::TokenStream
 }
 
 #[cfg(feature = "builder")]
-#[proc_macro_derive(Builder)]
+#[proc_macro_derive(Builder, attributes(builder))]
 /// Sets up a full builder implementation for a struct.
+///
+/// Fields are required unless they are `Option<T>` (the setter then takes a `T`)
+/// or marked with `#[builder(default)]` / `#[builder(default = expr)]`, where
+/// `expr` may refer to fields declared before it.
+///
+/// `build()` returns a generated `FooBuilderError` listing every missing field.
+/// Use `#[builder(error = MyError)]` to return your own error type instead; it
+/// must implement `From<FooBuilderError>`.
+///
+/// `#[builder(validate = path::to::fn)]` runs a check from `build()`: on a field
+/// it receives `&FieldType`, on the struct it receives the built value. The
+/// function returns `Result<(), E>` for any `E: Display`.
+///
+/// Setters take the field type as-is unless the field (or the whole struct) is
+/// marked `#[builder(into)]` or `#[builder(try_into)]`, or the field uses
+/// `#[builder(with = |a: A, b: B| ...)]` to accept the closure's parameters.
+///
+/// Collection fields marked `#[builder(each = "item")]` default to empty and
+/// also get `item(x)` and `extend_field(iter)` methods; map types take
+/// `item(key, value)`.
+///
+/// Setters consume and return the builder by default. `#[builder(pattern =
+/// "mutable")]` makes them take `&mut self` and `#[builder(pattern =
+/// "immutable")]` makes them clone from `&self`; both make `build(&self)`
+/// clone the fields so the builder can be reused.
+///
+/// A field whose type also derives `Builder` can be marked `#[builder(nested)]`
+/// to expose `field(&mut self) -> &mut FieldBuilder` and `field_with(f)`.
+/// `build()` then builds it too and reports its errors as `field.inner`. The
+/// nested type must use the generated error type, and one built with
+/// `try_build()` or `build_async()` is marked `#[builder(nested, fallible)]` or
+/// `#[builder(nested, async)]`. Calling setters through `field()` needs the
+/// nested builder to use `pattern = "mutable"`; with owned setters, use
+/// `field_with(|b| b.inner(..))` instead.
+///
+/// Built values convert back with `FooBuilder::from(foo)` or `foo.to_builder()`,
+/// and `builder.merge(other)` overlays the fields set on `other`, which is
+/// handy for layering defaults, config files and command line overrides.
+///
+/// With the `serde` feature enabled, `#[builder(serde)]` makes the builder
+/// derive `serde::Deserialize`, treating every field as optional, so partial
+/// configs can be loaded straight into a builder. The deriving crate needs
+/// `serde` with its `derive` feature as a dependency. `#[builder(serde(...))]`
+/// on a field forwards attributes like `skip` or `rename = "..."` to the
+/// builder's field. Typestate builders don't support it, since their required
+/// fields are tracked in the builder's type.
+///
+/// Setters carry the field's own doc comments. `#[builder(setter(name =
+/// "...", vis = "...", prefix = "..."))]` renames a setter or changes its
+/// visibility, and `#[builder(skip, default = ...)]` leaves a field out of the
+/// builder entirely.
+///
+/// Tuple structs get positional setters `_0`, `_1`, ..., which
+/// `#[builder(name = "...")]` on a field renames. On an enum, every variant
+/// with fields gets its own builder, so `Shape::Circle { .. }` is built with
+/// `Shape::circle_builder()` and `ShapeCircleBuilder`.
+///
+/// `#[builder(const)]` makes `builder()` and the setters `const fn` and adds a
+/// `const fn build_const()` that panics on a missing field, so builders can
+/// initialize `static`s and `const`s. Defaults must then be const expressions.
+///
+/// `#[builder(default_with = path)]` calls `path()` for an unset field. Adding
+/// `fallible` lets it return a `Result`, whose error `try_build()` reports as
+/// `InitFailed`, and adding `async` makes it an `async fn`, awaited by
+/// `build_async()`. These replace `build()` on builders that use them.
+///
+/// Add `#[builder(typestate)]` to the struct to track required fields in the
+/// builder's type, turning a forgotten setter into a compile error.
 pub fn derive_builder(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = syn::parse_macro_input!(input as syn::DeriveInput);
 
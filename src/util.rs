@@ -0,0 +1,58 @@
+#[cfg(any(feature = "builder", feature = "builder_lite"))]
+use syn::{DeriveInput, Field, Ident, Visibility};
+use syn::{GenericArgument, PathArguments, Type};
+
+/// Returns `T` if `ty` is spelled `Option<T>`.
+pub(crate) fn option_inner(ty: &Type) -> Option<&Type> {
+    if let Type::Path(type_path) = ty
+        && type_path.qself.is_none()
+        && let Some(last_seg) = type_path.path.segments.last()
+        && last_seg.ident == "Option"
+        && let PathArguments::AngleBracketed(args) = &last_seg.arguments
+        && args.args.len() == 1
+        && let Some(GenericArgument::Type(inner)) = args.args.first()
+    {
+        return Some(inner);
+    }
+
+    None
+}
+
+/// `CamelCase` variant name to `snake_case`, for method names like
+/// `Shape::circle_builder()` or `with_circle_radius()`. A run of capitals is
+/// one word, so `HTTPError` becomes `http_error`.
+#[cfg(any(feature = "builder", feature = "builder_lite"))]
+pub(crate) fn snake_case(ident: &Ident) -> String {
+    let name = ident.to_string();
+    let chars: Vec<char> = name.trim_start_matches("r#").chars().collect();
+    let mut snake = String::new();
+
+    for (idx, &ch) in chars.iter().enumerate() {
+        if ch.is_uppercase() {
+            let prev = idx.checked_sub(1).map(|i| chars[i]);
+            let next = chars.get(idx + 1);
+
+            if prev.is_some_and(|p| p.is_lowercase() || p.is_ascii_digit())
+                || (prev.is_some_and(char::is_uppercase) && next.is_some_and(|n| n.is_lowercase()))
+            {
+                snake.push('_');
+            }
+            snake.extend(ch.to_lowercase());
+        } else {
+            snake.push(ch);
+        }
+    }
+
+    snake
+}
+
+/// Visibility of a field's generated methods when none is configured. Enum
+/// variant fields can't have their own, so they are as visible as the enum.
+#[cfg(any(feature = "builder", feature = "builder_lite"))]
+pub(crate) fn inherited_vis<'a>(
+    input: &'a DeriveInput,
+    field: &'a Field,
+    in_variant: bool,
+) -> &'a Visibility {
+    if in_variant { &input.vis } else { &field.vis }
+}